@@ -0,0 +1,39 @@
+//! Where date dependent logic learns which day it is, so that it can be tested with a fixed date.
+
+/// The source of today's date.
+pub trait Clock {
+    fn today(&self) -> chrono::NaiveDate;
+}
+
+/// The system clock, read in 'timezone' or the local timezone of the machine if not set. See
+/// `Config::clock`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock {
+    pub timezone: Option<chrono_tz::Tz>,
+}
+
+impl SystemClock {
+    /// The date in 'timezone' at the moment 'now'.
+    pub fn date_at(&self, now: chrono::DateTime<chrono::Utc>) -> chrono::NaiveDate {
+        match self.timezone {
+            None => now.with_timezone(&chrono::Local).date_naive(),
+            Some(timezone) => now.with_timezone(&timezone).date_naive(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn today(&self) -> chrono::NaiveDate {
+        self.date_at(chrono::Utc::now())
+    }
+}
+
+/// A clock that is always on the same day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub chrono::NaiveDate);
+
+impl Clock for FixedClock {
+    fn today(&self) -> chrono::NaiveDate {
+        self.0
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{Config, FormatOptions};
-use crate::{Result, TaskpaperFile};
+use crate::{Error, Result, TaskpaperFile};
 use path_absolutize::Absolutize;
 use std::cmp;
 use std::collections::HashMap;
@@ -50,15 +50,10 @@ pub struct Match<'a> {
     pub node_id: crate::NodeId,
 }
 
-// TODO(hrapp): This seems messy - on the one site, this should be part of the Database, on the
-// other site this is used in the App too. It is also questionable if all files should be searched
-// or only one.
-pub fn search<'a>(
-    mut query: String,
-    sort_by: Option<&str>,
-    config: &Config,
-    files_map: &'a HashMap<PathBuf, impl AsRef<TaskpaperFile>>,
-) -> Result<Vec<Match<'a>>> {
+/// Replaces all aliases from 'config' in 'query' until no alias applies anymore. Expansion stops
+/// after 50 rounds to guard against aliases that expand into each other.
+pub fn expand_aliases(query: &str, config: &Config) -> String {
+    let mut query = query.to_string();
     'outer: for _ in 0..50 {
         for (key, value) in &config.aliases {
             let new_query = query.replace(key, value);
@@ -68,6 +63,19 @@ pub fn search<'a>(
             }
         }
     }
+    query
+}
+
+// TODO(hrapp): This seems messy - on the one site, this should be part of the Database, on the
+// other site this is used in the App too. It is also questionable if all files should be searched
+// or only one.
+pub fn search<'a>(
+    query: String,
+    sort_by: Option<&str>,
+    config: &Config,
+    files_map: &'a HashMap<PathBuf, impl AsRef<TaskpaperFile>>,
+) -> Result<Vec<Match<'a>>> {
+    let query = expand_aliases(&query, config);
 
     let sort_order = sort_by.as_ref().map(|s| {
         let mut res = Vec::new();
@@ -146,43 +154,168 @@ pub fn search<'a>(
     Ok(matches)
 }
 
+/// Files that could not be parsed, with the reason.
+pub type ParseErrors = Vec<(PathBuf, Error)>;
+
 /// A folder containing many Taskpaper files. Some of which are special, like inbox, timeline.
 #[derive(Debug)]
 pub struct Database {
     pub root: PathBuf,
+
+    /// Files parsed through 'reparse_file' or 'reparse_all_files', keyed by relative path.
+    files: HashMap<PathBuf, TaskpaperFile>,
 }
 
 impl Database {
+    /// Opens the database in 'dir'. Returns `Error::DatabaseNotFound` if 'dir' is not an existing
+    /// directory.
     pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        if !dir.as_ref().is_dir() {
+            return Err(Error::DatabaseNotFound(dir.as_ref().to_path_buf()));
+        }
         let root = dir.as_ref().absolutize()?.to_path_buf();
-        Ok(Self { root })
+        Ok(Self {
+            root,
+            files: HashMap::new(),
+        })
+    }
+
+    /// The files that have been parsed into the cache of this database. Nothing is parsed on
+    /// construction, use 'reparse_all_files' or 'reparse_file' to fill the cache.
+    pub fn files(&self) -> &HashMap<PathBuf, TaskpaperFile> {
+        &self.files
+    }
+
+    /// Replaces the cache with all files currently in the database. Returns the files that could
+    /// not be parsed.
+    pub fn reparse_all_files(&mut self) -> Result<ParseErrors> {
+        let (files, errors) = self.parse_all_files_collect_errors()?;
+        self.files = files;
+        Ok(errors)
+    }
+
+    /// Updates the cached entry for 'path', which can be absolute or relative to the root. If the
+    /// file no longer exists, it is removed from the cache.
+    pub fn reparse_file(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let relative_path = if path.is_absolute() {
+            self.relative(path)
+                .ok_or_else(|| Error::NotInDatabase(path.to_path_buf()))?
+        } else {
+            path.to_path_buf()
+        };
+        let full_path = self.root.join(&relative_path);
+        if !full_path.exists() {
+            self.files.remove(&relative_path);
+            return Ok(());
+        }
+        let file = TaskpaperFile::parse_file(&full_path)?;
+        self.files.insert(relative_path, file);
+        Ok(())
     }
 
     pub fn config(&self) -> Result<Config> {
         let data = std::fs::read_to_string(self.root.join(".config.toml"))?;
-        Ok(toml::from_str(&data).map_err(|e| crate::Error::InvalidConfig(e.to_string()))?)
+        Ok(toml::from_str(&data).map_err(|e| Error::InvalidConfig(e.to_string()))?)
     }
 
+    /// Like 'config', but returns None if the database has no config file. A config that cannot be
+    /// read or parsed is still an error.
+    fn config_if_exists(&self) -> Result<Option<Config>> {
+        if !self.root.join(".config.toml").exists() {
+            return Ok(None);
+        }
+        self.config().map(Some)
+    }
+
+    /// Parses all files in the database. Files that cannot be parsed are skipped with a message
+    /// on stdout, use `parse_all_files_collect_errors` to handle them yourself.
     pub fn parse_all_files(&self) -> Result<HashMap<PathBuf, TaskpaperFile>> {
+        let (files, errors) = self.parse_all_files_collect_errors()?;
+        for (path, _) in errors {
+            println!("Skipping {:?} due to parsing errors.", path);
+        }
+        Ok(files)
+    }
+
+    /// Parses all files in the database. Returns the successfully parsed files and the errors for
+    /// all files that could not be read or parsed, both keyed by their relative path.
+    pub fn parse_all_files_collect_errors(
+        &self,
+    ) -> Result<(HashMap<PathBuf, TaskpaperFile>, ParseErrors)> {
+        let (paths, mut errors) = self.walk_files()?;
+        let paths = paths
+            .into_iter()
+            .map(|relative_path| {
+                let path = self.root.join(&relative_path);
+                (relative_path, path)
+            })
+            .collect();
+
         let mut files = HashMap::new();
-        for entry in WalkDir::new(&self.root) {
-            if entry.is_err() {
-                continue;
+        for (relative_path, result) in parse_files(paths) {
+            match result {
+                Ok(file) => {
+                    files.insert(relative_path, file);
+                }
+                Err(e) => errors.push((relative_path, e)),
+            }
+        }
+        Ok((files, errors))
+    }
+
+    /// Returns the relative paths of all taskpaper files in the database, without the ones in
+    /// 'excluded_dirs'. Directory entries that cannot be read are skipped.
+    pub fn list_files(&self) -> Result<Vec<PathBuf>> {
+        Ok(self.walk_files()?.0)
+    }
+
+    /// Returns the relative paths of all taskpaper files outside of 'excluded_dirs' and the errors
+    /// for all directory entries that could not be read.
+    fn walk_files(&self) -> Result<(Vec<PathBuf>, ParseErrors)> {
+        let excluded_dirs = match self.config_if_exists()? {
+            Some(config) => config
+                .excluded_dirs
+                .iter()
+                .map(|p| glob::Pattern::new(p))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| Error::InvalidConfig(e.to_string()))?,
+            None => Vec::new(),
+        };
+        let is_excluded = |entry: &walkdir::DirEntry| {
+            if !entry.file_type().is_dir() {
+                return false;
             }
-            let entry = entry.unwrap();
+            let relative_path = entry.path().strip_prefix(&self.root).unwrap();
+            excluded_dirs.iter().any(|pattern| {
+                pattern.matches_path(relative_path)
+                    || pattern.matches_path(Path::new(entry.file_name()))
+            })
+        };
+
+        let mut paths = Vec::new();
+        let mut errors = Vec::new();
+        for entry in WalkDir::new(&self.root)
+            .into_iter()
+            .filter_entry(|e| !is_excluded(e))
+        {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    if let Some(path) = e.path() {
+                        let relative_path = path.strip_prefix(&self.root).unwrap_or(path);
+                        errors.push((relative_path.to_path_buf(), Error::Io(e.into())));
+                    }
+                    continue;
+                }
+            };
             let path = entry.path();
             if path.extension() != Some(OsStr::new("taskpaper")) {
                 continue;
             }
-            let file = TaskpaperFile::parse_file(path);
-            if file.is_err() {
-                println!("Skipping {:?} due to parsing errors.", path);
-                continue;
-            }
-            let relative_path = entry.path().strip_prefix(&self.root).unwrap().to_path_buf();
-            files.insert(relative_path, file.unwrap());
+            paths.push(path.strip_prefix(&self.root).unwrap().to_path_buf());
         }
-        Ok(files)
+        Ok((paths, errors))
     }
 
     /// Returns the name (i.e. relative path) of 'path' inside of the database.
@@ -202,8 +335,21 @@ impl Database {
         }
     }
 
+    /// Parses the common file 'kind'. Returns an I/O error if it does not exist.
     pub fn parse_common_file(&self, kind: CommonFileKind) -> Result<TaskpaperFile> {
-        TaskpaperFile::parse_file(kind.find(&self.root).expect("Common file not found!"))
+        TaskpaperFile::parse_file(self.expected_path_of_common_file(kind)?)
+    }
+
+    /// Like 'parse_common_file', but returns an empty file with the path of the common file if it
+    /// does not exist yet, e.g. the inbox of a fresh database.
+    pub fn parse_or_create_common_file(&self, kind: CommonFileKind) -> Result<TaskpaperFile> {
+        let path = self.expected_path_of_common_file(kind)?;
+        if path.exists() {
+            return TaskpaperFile::parse_file(path);
+        }
+        let mut tpf = TaskpaperFile::new();
+        tpf.path = Some(path);
+        Ok(tpf)
     }
 
     pub fn get_format_for_filename(&self, path: impl AsRef<Path>) -> Result<FormatOptions> {
@@ -221,45 +367,118 @@ impl Database {
         Ok(FormatOptions::default())
     }
 
-    pub fn overwrite_common_file(&self, tpf: &TaskpaperFile, kind: CommonFileKind) -> Result<()> {
-        let format = self.get_format_for_filename(&kind.to_path_buf())?;
-        tpf.write(
-            kind.find(&self.root).expect("Common file not found!"),
-            format,
-        )
+    /// Writes 'tpf' as the common file 'kind' with its configured format. Returns true if the file
+    /// changed.
+    pub fn overwrite_common_file(&self, tpf: &TaskpaperFile, kind: CommonFileKind) -> Result<bool> {
+        let path = self.expected_path_of_common_file(kind)?;
+        let format = self.get_format_for_filename(&path)?;
+        tpf.write(path, format)
+    }
+
+    /// Returns the path of the common file 'kind' if it exists. The name can be overridden in the
+    /// 'common_files' section of the config.
+    pub fn path_of_common_file(&self, kind: CommonFileKind) -> Result<Option<PathBuf>> {
+        Ok(Some(self.expected_path_of_common_file(kind)?).filter(|path| path.exists()))
+    }
+
+    /// The path the common file 'kind' has or would have, whether it exists or not. Fails if the
+    /// config cannot be loaded, rather than falling back to the default names.
+    fn expected_path_of_common_file(&self, kind: CommonFileKind) -> Result<PathBuf> {
+        Ok(kind.path(&self.root, self.config_if_exists()?.as_ref()))
+    }
+
+    /// Returns the path and node of the item in 'files' whose '@id' tag has the value 'id'.
+    pub fn find_by_id(
+        &self,
+        files: &HashMap<PathBuf, impl AsRef<TaskpaperFile>>,
+        id: &str,
+    ) -> Option<(PathBuf, crate::NodeId)> {
+        for (path, tpf) in files {
+            for node in tpf.as_ref() {
+                if node.item().tags().get_value("id") == Some(id) {
+                    return Some((path.clone(), node.id().clone()));
+                }
+            }
+        }
+        None
     }
 
-    pub fn path_of_common_file(&self, kind: CommonFileKind) -> Option<PathBuf> {
-        kind.find(&self.root)
+    /// Atomically writes all 'files', keyed by path relative to the root, using the format
+    /// configured for each of them. Unchanged files are not touched.
+    pub fn write_all(&self, files: &HashMap<PathBuf, TaskpaperFile>) -> Result<()> {
+        for (path, tpf) in files {
+            let format = self.get_format_for_filename(path)?;
+            tpf.write_atomically(self.root.join(path), format)?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Parses the files at the absolute paths in 'paths', keeping the relative paths next to the
+/// results in the same order.
+#[cfg(feature = "parallel")]
+fn parse_files(paths: Vec<(PathBuf, PathBuf)>) -> Vec<(PathBuf, Result<TaskpaperFile>)> {
+    use rayon::prelude::*;
+    paths
+        .into_par_iter()
+        .map(|(relative_path, path)| (relative_path, TaskpaperFile::parse_file(path)))
+        .collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn parse_files(paths: Vec<(PathBuf, PathBuf)>) -> Vec<(PathBuf, Result<TaskpaperFile>)> {
+    parse_files_sequentially(paths)
+}
+
+fn parse_files_sequentially(
+    paths: Vec<(PathBuf, PathBuf)>,
+) -> Vec<(PathBuf, Result<TaskpaperFile>)> {
+    paths
+        .into_iter()
+        .map(|(relative_path, path)| (relative_path, TaskpaperFile::parse_file(path)))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CommonFileKind {
     Inbox,
     Todo,
     Tickle,
     Logbook,
     Timeline,
+    Checkout,
 }
 
 impl CommonFileKind {
-    fn find(&self, root: &Path) -> Option<PathBuf> {
-        let path = root.join(self.to_path_buf());
-        if path.exists() {
-            Some(path)
-        } else {
-            None
+    fn path(&self, root: &Path, config: Option<&Config>) -> PathBuf {
+        let configured = config.and_then(|c| c.common_files.get(self.name()));
+        match configured {
+            Some(p) => root.join(p),
+            None => root.join(self.to_path_buf()),
         }
     }
 
-    fn to_path_buf(&self) -> PathBuf {
+    /// The key of this kind in the 'common_files' section of the config.
+    fn name(&self) -> &'static str {
         match *self {
+            CommonFileKind::Inbox => "inbox",
+            CommonFileKind::Todo => "todo",
+            CommonFileKind::Tickle => "tickle",
+            CommonFileKind::Logbook => "logbook",
+            CommonFileKind::Timeline => "timeline",
+            CommonFileKind::Checkout => "checkout",
+        }
+    }
+
+    /// The default path of this kind, relative to the database root.
+    pub(crate) fn to_path_buf(self) -> PathBuf {
+        match self {
             CommonFileKind::Inbox => PathBuf::from("01_inbox.taskpaper"),
             CommonFileKind::Todo => PathBuf::from("02_todo.taskpaper"),
             CommonFileKind::Tickle => PathBuf::from("03_tickle.taskpaper"),
             CommonFileKind::Logbook => PathBuf::from("40_logbook.taskpaper"),
             CommonFileKind::Timeline => PathBuf::from("10_timeline.taskpaper"),
+            CommonFileKind::Checkout => PathBuf::from("20_checkout.taskpaper"),
         }
     }
 }
@@ -267,7 +486,285 @@ impl CommonFileKind {
 #[cfg(test)]
 mod tests {
     use crate::testing::DatabaseTest;
-    use crate::CommonFileKind;
+    use crate::{CommonFileKind, Database, Error};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_from_dir_not_found() {
+        let t = DatabaseTest::new();
+        let missing = t.write_file("file.taskpaper", "").with_file_name("missing");
+        match Database::from_dir(&missing) {
+            Err(Error::DatabaseNotFound(path)) => assert_eq!(missing, path),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let file = t.write_file("file.taskpaper", "");
+        assert!(matches!(
+            Database::from_dir(&file),
+            Err(Error::DatabaseNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_all_files_collect_errors() {
+        let mut t = DatabaseTest::new();
+        t.write_file("good.taskpaper", "- A task @next\n");
+        let bad = t.write_file("bad.taskpaper", "");
+        std::fs::write(&bad, b"- Not utf-8 \xff\xfe\n").unwrap();
+
+        let db = t.read_database();
+        let (files, errors) = db.parse_all_files_collect_errors().unwrap();
+        assert_eq!(1, files.len());
+        assert!(files.contains_key(&PathBuf::from("good.taskpaper")));
+        assert_eq!(1, errors.len());
+        assert_eq!(PathBuf::from("bad.taskpaper"), errors[0].0);
+        assert!(matches!(errors[0].1, Error::Io(_)));
+    }
+
+    #[test]
+    fn test_parse_all_files_excluded_dirs() {
+        let mut t = DatabaseTest::new();
+        t.write_config("excluded_dirs = ['archive', 'projects/old*']");
+        t.write_file("todo.taskpaper", "- A task\n");
+        t.write_file("archive/2020.taskpaper", "- Archived\n");
+        t.write_file("projects/archive/notes.taskpaper", "- Nested archive\n");
+        t.write_file("projects/new.taskpaper", "- New\n");
+        t.write_file("projects/old_stuff/old.taskpaper", "- Old\n");
+
+        let db = t.read_database();
+        let mut paths = db
+            .parse_all_files()
+            .unwrap()
+            .into_keys()
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            vec![
+                PathBuf::from("projects/new.taskpaper"),
+                PathBuf::from("todo.taskpaper")
+            ],
+            paths
+        );
+
+        t.write_file(".config.toml", "excluded_dirs = [");
+        let db = t.read_database();
+        assert!(matches!(db.list_files(), Err(Error::InvalidConfig(_))));
+        assert!(matches!(db.parse_all_files(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_parse_matches_sequential_parse() {
+        let mut t = DatabaseTest::new();
+        for i in 0..20 {
+            t.write_file(
+                format!("dir_{}/file_{}.taskpaper", i % 3, i),
+                &format!(
+                    "Project {}:\n\t- Task {} @due(2024-01-{:02})\n",
+                    i,
+                    i,
+                    i + 1
+                ),
+            );
+        }
+        let db = t.read_database();
+        let paths = db
+            .list_files()
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.clone(), db.root.join(p)))
+            .collect::<Vec<_>>();
+
+        let parallel = super::parse_files(paths.clone());
+        let sequential = super::parse_files_sequentially(paths);
+        assert_eq!(20, parallel.len());
+        let options = crate::FormatOptions::default();
+        for ((p_path, p_result), (s_path, s_result)) in parallel.into_iter().zip(sequential) {
+            assert_eq!(s_path, p_path);
+            assert_eq!(
+                s_result.unwrap().to_string(options),
+                p_result.unwrap().to_string(options)
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_all() {
+        let t = DatabaseTest::new();
+        t.write_config("");
+        let a = t.write_file("a.taskpaper", "- A task   @next\n");
+        std::fs::create_dir(a.with_file_name("sub")).unwrap();
+        t.write_file("sub/b.taskpaper", "- B task\n");
+        let db = Database::from_dir(a.parent().unwrap()).unwrap();
+        let mut files = db.parse_all_files().unwrap();
+        files
+            .get_mut(&PathBuf::from("sub/b.taskpaper"))
+            .unwrap()
+            .insert(
+                crate::Item::new(crate::ItemKind::Task, "New task".to_string()),
+                crate::Position::AsLast,
+            );
+        db.write_all(&files).unwrap();
+
+        assert_eq!("- A task @next\n", t.read_file("a.taskpaper"));
+        assert_eq!("- B task\n- New task\n", t.read_file("sub/b.taskpaper"));
+        let mut entries = std::fs::read_dir(db.root.join("sub"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(vec!["b.taskpaper"], entries);
+    }
+
+    #[test]
+    fn test_find_by_id() {
+        let mut t = DatabaseTest::new();
+        t.write_file("a.taskpaper", "- A task @id(a1)\n");
+        t.write_file(
+            "b.taskpaper",
+            "Project: @id(b1)\n\t- B task @id(b2)\n\t- Other task @id\n",
+        );
+        let db = t.read_database();
+        let files = db.parse_all_files().unwrap();
+
+        let (path, node_id) = db.find_by_id(&files, "b2").unwrap();
+        assert_eq!(PathBuf::from("b.taskpaper"), path);
+        assert_eq!("B task", files[&path][&node_id].item().text());
+
+        let (path, _) = db.find_by_id(&files, "a1").unwrap();
+        assert_eq!(PathBuf::from("a.taskpaper"), path);
+        assert!(db.find_by_id(&files, "missing").is_none());
+    }
+
+    #[test]
+    fn test_expand_aliases() {
+        let t = DatabaseTest::new();
+        let config = t.write_config(
+            "[aliases]\n'@work' = '@area = work'\n'@open' = '(not @done and @work)'\n",
+        );
+        let db = Database::from_dir(config.parent().unwrap()).unwrap();
+        assert_eq!(
+            "(not @done and @area = work) and @next",
+            crate::db::expand_aliases("@open and @next", &db.config().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_reparse_file() {
+        let t = DatabaseTest::new();
+        let a = t.write_file("a.taskpaper", "- A task\n");
+        t.write_file("b.taskpaper", "- B task\n");
+        let mut db = Database::from_dir(a.parent().unwrap()).unwrap();
+        assert!(db.files().is_empty());
+        assert!(db.reparse_all_files().unwrap().is_empty());
+        assert_eq!(2, db.files().len());
+
+        let text = |db: &Database, path: &str| {
+            db.files()[&PathBuf::from(path)].to_string(crate::FormatOptions::default())
+        };
+
+        t.write_file("a.taskpaper", "- A changed task\n");
+        t.write_file("b.taskpaper", "- B changed task\n");
+        db.reparse_file("a.taskpaper").unwrap();
+        assert_eq!("- A changed task\n", text(&db, "a.taskpaper"));
+        assert_eq!("- B task\n", text(&db, "b.taskpaper"));
+
+        let b = db.root.join("b.taskpaper");
+        std::fs::remove_file(&b).unwrap();
+        db.reparse_file(&b).unwrap();
+        assert_eq!(1, db.files().len());
+
+        assert!(matches!(
+            db.reparse_file("/not/in/database.taskpaper"),
+            Err(Error::NotInDatabase(_))
+        ));
+    }
+
+    #[test]
+    fn test_configured_common_file() {
+        let t = DatabaseTest::new();
+        let config = t.write_config("");
+        t.write_file("inbox.taskpaper", "- custom inbox task\n");
+        t.write_file(
+            CommonFileKind::Inbox.to_path_buf(),
+            "- default inbox task\n",
+        );
+        let db = Database::from_dir(config.parent().unwrap()).unwrap();
+        assert_eq!(
+            Some(db.root.join("01_inbox.taskpaper")),
+            db.path_of_common_file(CommonFileKind::Inbox).unwrap()
+        );
+
+        t.write_config("[common_files]\ninbox = 'inbox.taskpaper'\n");
+        assert_eq!(
+            Some(db.root.join("inbox.taskpaper")),
+            db.path_of_common_file(CommonFileKind::Inbox).unwrap()
+        );
+        let inbox = db.parse_common_file(CommonFileKind::Inbox).unwrap();
+        assert_eq!(
+            "- custom inbox task\n",
+            inbox.to_string(crate::FormatOptions::default())
+        );
+        assert_eq!(None, db.path_of_common_file(CommonFileKind::Todo).unwrap());
+
+        // A broken config must not silently fall back to the default names.
+        t.write_file(".config.toml", "[common_files");
+        assert!(matches!(
+            db.path_of_common_file(CommonFileKind::Inbox),
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            db.parse_common_file(CommonFileKind::Inbox),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_or_create_common_file() {
+        let t = DatabaseTest::new();
+        let config = t.write_config("");
+        let db = Database::from_dir(config.parent().unwrap()).unwrap();
+        assert!(matches!(
+            db.parse_common_file(CommonFileKind::Inbox),
+            Err(Error::Io(_))
+        ));
+
+        let mut inbox = db
+            .parse_or_create_common_file(CommonFileKind::Inbox)
+            .unwrap();
+        let inbox_path = db.root.join(CommonFileKind::Inbox.to_path_buf());
+        assert_eq!(Some(inbox_path.as_path()), inbox.path());
+        assert!(inbox.iter().next().is_none());
+
+        inbox.insert(
+            crate::Item::new(crate::ItemKind::Task, "First task".to_string()),
+            crate::Position::AsLast,
+        );
+        assert!(db
+            .overwrite_common_file(&inbox, CommonFileKind::Inbox)
+            .unwrap());
+        assert_eq!(
+            "- First task\n",
+            std::fs::read_to_string(&inbox_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_path_of_checkout() {
+        let t = DatabaseTest::new();
+        let todo = t.write_file(CommonFileKind::Todo.to_path_buf(), "");
+        let db = Database::from_dir(todo.parent().unwrap()).unwrap();
+        assert_eq!(
+            None,
+            db.path_of_common_file(CommonFileKind::Checkout).unwrap()
+        );
+
+        let checkout = t.write_file(CommonFileKind::Checkout.to_path_buf(), "");
+        assert_eq!(
+            Some(checkout),
+            db.path_of_common_file(CommonFileKind::Checkout).unwrap()
+        );
+    }
 
     // TODO(sirver): Actually add a few tests for tickling, timeline and so on?
     #[test]
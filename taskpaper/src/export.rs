@@ -0,0 +1,478 @@
+//! Conversions of a `TaskpaperFile` from and into other formats.
+
+use crate::tag::extract_tags;
+use crate::{
+    ordered_tags, Clock, Item, ItemKind, NodeId, Position, Result, Tag, TagOrder, TaskpaperFile,
+};
+use std::collections::HashSet;
+
+/// The kind of Markdown block a line belongs to. Blocks of different kind are separated by an
+/// empty line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MarkdownBlock {
+    Heading,
+    List,
+    Paragraph,
+}
+
+/// Returns the tags of 'item' as inline code spans with a leading space, e.g. " `@due(today)`".
+fn markdown_tags(item: &Item, order: TagOrder) -> String {
+    ordered_tags(item, order)
+        .iter()
+        .map(|(tag, _)| format!(" `{}`", tag))
+        .collect()
+}
+
+/// Removes the backticks around code spans that contain a tag, e.g. '`@due(today)`', so that the
+/// tag is found by 'extract_tags'.
+fn unwrap_tag_spans(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("`@") {
+        let len = match rest[start + 1..].find('`') {
+            Some(len) => len,
+            None => break,
+        };
+        out.push_str(&rest[..start]);
+        out.push_str(&rest[start + 1..start + 1 + len]);
+        rest = &rest[start + 2 + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns the width of the leading whitespace of 'line', counting a tab as 4 spaces.
+fn markdown_indent(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// Escapes 'text' for use as iCalendar property value.
+fn ical_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Appends 'line' to 'out', folded into lines of at most 75 bytes as iCalendar requires.
+fn append_ical_line(out: &mut String, line: &str) {
+    let mut line_start = 0;
+    let mut max_len = 75;
+    for (index, c) in line.char_indices() {
+        if index + c.len_utf8() - line_start > max_len {
+            out.push_str(&line[line_start..index]);
+            out.push_str("\r\n ");
+            line_start = index;
+            // The leading space of continuation lines counts.
+            max_len = 74;
+        }
+    }
+    out.push_str(&line[line_start..]);
+    out.push_str("\r\n");
+}
+
+/// Returns an iCalendar with one VTODO for every task matching 'query' that has a '@due' date.
+/// The text of the task becomes the summary and the projects it is in become its categories.
+/// Tasks without '@due' or with a value that is not a '%Y-%m-%d' date are skipped. The todos are
+/// stamped with the start of today according to 'clock', so that exporting the same file on the
+/// same day gives the same output.
+pub fn tasks_to_ical(tpf: &TaskpaperFile, query: &str, clock: &dyn Clock) -> Result<String> {
+    let now = clock.today().format("%Y%m%dT000000Z").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//SirVer//taskpaper//EN".to_string(),
+    ];
+    for node_id in tpf.search(query)? {
+        let item = tpf[&node_id].item();
+        let due = match item.tags().value_as_date("due") {
+            Some(due) if item.is_task() => due,
+            _ => continue,
+        };
+        let mut projects = tpf
+            .ancestors(&node_id)
+            .filter(|ancestor| ancestor.item().is_project())
+            .map(|ancestor| ancestor.item().text().to_string())
+            .collect::<Vec<_>>();
+        projects.reverse();
+
+        // The uid needs to stay the same between exports so that calendars update the todo
+        // instead of adding a new one.
+        let uid = sha1_smol::Sha1::from(format!("{}\n{}", projects.join("\n"), item.text()))
+            .digest()
+            .to_string();
+        lines.push("BEGIN:VTODO".to_string());
+        lines.push(format!("UID:{}@taskpaper", uid));
+        lines.push(format!("DTSTAMP:{}", now));
+        lines.push(format!("SUMMARY:{}", ical_escape(item.text())));
+        lines.push(format!("DTSTART;VALUE=DATE:{}", due.format("%Y%m%d")));
+        lines.push(format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")));
+        if !projects.is_empty() {
+            let categories = projects
+                .iter()
+                .map(|p| ical_escape(p))
+                .collect::<Vec<_>>()
+                .join(",");
+            lines.push(format!("CATEGORIES:{}", categories));
+        }
+        if item.tags().contains("done") {
+            lines.push("STATUS:COMPLETED".to_string());
+        }
+        lines.push("END:VTODO".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut out = String::new();
+    for line in lines {
+        append_ical_line(&mut out, &line);
+    }
+    Ok(out)
+}
+
+/// Quotes 'value' for a CSV cell if it contains a separator, a quote or a line break.
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes 'text' for use in an XML attribute value.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Appends the outline elements for 'node_ids' and their children to 'out'.
+fn append_opml_outlines(tpf: &TaskpaperFile, node_ids: &[NodeId], depth: usize, out: &mut String) {
+    for node_id in node_ids {
+        let node = &tpf[node_id];
+        let item = node.item();
+        let (kind, order) = match item.kind {
+            ItemKind::Project => ("project", TagOrder::Alphabetical),
+            ItemKind::Task => ("task", TagOrder::ValuelessFirst),
+            ItemKind::Note | ItemKind::RawLine => ("note", TagOrder::AsWritten),
+        };
+        let indent = "  ".repeat(depth);
+        out.push_str(&format!(
+            "{}<outline text=\"{}\" _type=\"{}\"",
+            indent,
+            xml_escape(item.text()),
+            kind
+        ));
+        let mut seen = HashSet::new();
+        for (tag, _) in ordered_tags(item, order) {
+            // An attribute can only be given once, so only the first of repeated tags is kept.
+            if !seen.insert(tag.name.clone()) {
+                continue;
+            }
+            out.push_str(&format!(
+                " data-{}=\"{}\"",
+                tag.name,
+                xml_escape(tag.value.as_deref().unwrap_or_default())
+            ));
+        }
+        if node.is_leaf() {
+            out.push_str("/>\n");
+        } else {
+            out.push_str(">\n");
+            append_opml_outlines(tpf, node.children(), depth + 1, out);
+            out.push_str(&format!("{}</outline>\n", indent));
+        }
+    }
+}
+
+impl TaskpaperFile {
+    /// Parses a Markdown document, like the ones written by 'to_markdown'. Headings become
+    /// projects, nested by their level. List items become tasks, nested by their indentation, and
+    /// checked items ('- [x]') get a '@done' tag. All other lines become notes of the heading or
+    /// list item they belong to. Tags can be written plain or as code spans.
+    pub fn from_markdown(input: &str) -> Result<Self> {
+        let mut tpf = TaskpaperFile::new();
+        let mut headings: Vec<(usize, NodeId)> = Vec::new();
+        let mut list_items: Vec<(usize, NodeId)> = Vec::new();
+        for line in input.lines().filter(|line| !line.trim().is_empty()) {
+            let width = markdown_indent(line);
+            let content = line.trim();
+            let level = content.chars().take_while(|c| *c == '#').count();
+            let (kind, text, checked) = if level > 0 && content[level..].starts_with(' ') {
+                while matches!(headings.last(), Some((l, _)) if *l >= level) {
+                    headings.pop();
+                }
+                list_items.clear();
+                (ItemKind::Project, &content[level..], false)
+            } else {
+                let list_item = ["- ", "* ", "+ "]
+                    .iter()
+                    .find_map(|bullet| content.strip_prefix(bullet));
+                if list_item.is_some() || width > 0 {
+                    while matches!(list_items.last(), Some((w, _)) if *w >= width) {
+                        list_items.pop();
+                    }
+                } else {
+                    list_items.clear();
+                }
+                match list_item {
+                    None => (ItemKind::Note, content, false),
+                    Some(text) => match text.get(..4) {
+                        Some("[ ] ") => (ItemKind::Task, &text[4..], false),
+                        Some("[x] ") | Some("[X] ") => (ItemKind::Task, &text[4..], true),
+                        _ => (ItemKind::Task, text, false),
+                    },
+                }
+            };
+
+            let (text, mut tags) = extract_tags(unwrap_tag_spans(text));
+            if checked && !tags.contains("done") {
+                tags.insert(Tag::new("done".to_string(), None));
+            }
+            let item = Item::new_with_tags(kind.clone(), text.trim().to_string(), tags);
+            let parent = list_items.last().or_else(|| headings.last());
+            let node_id = match parent {
+                Some((_, parent_id)) => tpf.insert(item, Position::AsLastChildOf(parent_id)),
+                None => tpf.insert(item, Position::AsLast),
+            };
+            match kind {
+                ItemKind::Project => headings.push((level, node_id)),
+                ItemKind::Task => list_items.push((width, node_id)),
+                _ => (),
+            }
+        }
+        Ok(tpf)
+    }
+
+    /// Renders this file as OPML 2.0 with one nested 'outline' element per item. The kind of the
+    /// item is in the '_type' attribute. Tags become 'data-name' attributes, as TaskPaper names
+    /// them, since an XML attribute name cannot start with '@'. Tags without value get an empty
+    /// one.
+    pub fn to_opml(&self) -> String {
+        let title = self
+            .path()
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Taskpaper".to_string());
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<opml version=\"2.0\">\n");
+        out.push_str(&format!(
+            "  <head>\n    <title>{}</title>\n  </head>\n",
+            xml_escape(&title)
+        ));
+        out.push_str("  <body>\n");
+        append_opml_outlines(self, &self.nodes, 2, &mut out);
+        out.push_str("  </body>\n</opml>\n");
+        out
+    }
+
+    /// Returns the items matching 'query' as CSV, with a header row of 'columns' followed by one
+    /// row per item. A column is either 'text', 'type' (project, task, note or raw), 'line' (the
+    /// line number in the parsed file, starting at 1) or the name of a tag, which is filled with
+    /// its value. Tags that are missing or have no value give an empty cell.
+    pub fn search_to_csv(&self, query: &str, columns: &[&str]) -> Result<String> {
+        let mut rows = vec![columns.iter().map(|c| c.to_string()).collect::<Vec<_>>()];
+        for node_id in self.search(query)? {
+            let item = self[&node_id].item();
+            let row = columns
+                .iter()
+                .map(|column| match *column {
+                    "text" => item.text().to_string(),
+                    "type" => match item.kind {
+                        ItemKind::Project => "project",
+                        ItemKind::Task => "task",
+                        ItemKind::Note => "note",
+                        ItemKind::RawLine => "raw",
+                    }
+                    .to_string(),
+                    "line" => item
+                        .line_index()
+                        .map(|index| (index + 1).to_string())
+                        .unwrap_or_default(),
+                    tag => item.tags().get_value(tag).unwrap_or_default().to_string(),
+                })
+                .collect();
+            rows.push(row);
+        }
+
+        let mut out = String::new();
+        for row in rows {
+            let cells = row.iter().map(|cell| csv_escape(cell)).collect::<Vec<_>>();
+            out.push_str(&cells.join(","));
+            out.push_str("\r\n");
+        }
+        Ok(out)
+    }
+
+    /// Renders this file as Markdown. Projects become headings, one level deeper for every
+    /// project they are nested in. Tasks become a checklist, nested like in the file and checked
+    /// if they are '@done'. Notes become paragraphs, or are indented into the list if they belong
+    /// to a task. Tags are written as code spans after the text.
+    pub fn to_markdown(&self) -> String {
+        let mut lines: Vec<(MarkdownBlock, String)> = Vec::new();
+        for node in self.iter() {
+            let item = node.item();
+            // Tasks and notes are nested below the tasks between them and their nearest project.
+            let num_tasks_above = self
+                .ancestors(node.id())
+                .take_while(|ancestor| !ancestor.item().is_project())
+                .filter(|ancestor| ancestor.item().is_task())
+                .count();
+            let indent = "  ".repeat(num_tasks_above);
+            let line = match item.kind {
+                ItemKind::Project => {
+                    let level = 1 + self
+                        .ancestors(node.id())
+                        .filter(|ancestor| ancestor.item().is_project())
+                        .count();
+                    let heading = format!(
+                        "{} {}{}",
+                        "#".repeat(level.min(6)),
+                        item.text(),
+                        markdown_tags(item, TagOrder::Alphabetical)
+                    );
+                    (MarkdownBlock::Heading, heading)
+                }
+                ItemKind::Task => {
+                    let checkbox = if item.tags().contains("done") {
+                        "[x]"
+                    } else {
+                        "[ ]"
+                    };
+                    let task = format!(
+                        "{}- {} {}{}",
+                        indent,
+                        checkbox,
+                        item.text(),
+                        markdown_tags(item, TagOrder::ValuelessFirst)
+                    );
+                    (MarkdownBlock::List, task)
+                }
+                ItemKind::Note | ItemKind::RawLine if num_tasks_above > 0 => {
+                    (MarkdownBlock::List, format!("{}{}", indent, item.text()))
+                }
+                ItemKind::Note | ItemKind::RawLine => {
+                    (MarkdownBlock::Paragraph, item.text().to_string())
+                }
+            };
+            lines.push(line);
+        }
+
+        let mut out = String::new();
+        let mut previous_block = None;
+        for (block, line) in lines {
+            if let Some(previous_block) = previous_block {
+                if block == MarkdownBlock::Heading || block != previous_block {
+                    out.push('\n');
+                }
+            }
+            out.push_str(&line);
+            out.push('\n');
+            previous_block = Some(block);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::DatabaseTest;
+    use crate::FormatOptions;
+
+    #[test]
+    fn test_to_markdown() {
+        let test = DatabaseTest::new();
+        let tpf = TaskpaperFile::parse(include_str!("tests/export/in.taskpaper")).unwrap();
+        test.write_file("out.md", &tpf.to_markdown());
+        test.assert_eq_to_golden("src/tests/export/markdown_out.md", "out.md");
+    }
+
+    #[test]
+    fn test_from_markdown() {
+        let tpf =
+            TaskpaperFile::from_markdown(include_str!("tests/export/from_markdown_in.md")).unwrap();
+        assert_eq!(
+            include_str!("tests/export/from_markdown_out.taskpaper"),
+            tpf.to_string(FormatOptions::default())
+        );
+
+        // Converting back what 'to_markdown' wrote gives the original file.
+        let tpf =
+            TaskpaperFile::from_markdown(include_str!("tests/export/markdown_out.md")).unwrap();
+        assert_eq!(
+            TaskpaperFile::parse(include_str!("tests/export/in.taskpaper"))
+                .unwrap()
+                .to_string(FormatOptions::default()),
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_to_opml() {
+        let test = DatabaseTest::new();
+        let mut input = include_str!("tests/export/in.taskpaper").to_string();
+        input.push_str("\t- Compare <a> & \"b\"\n");
+        let tpf = TaskpaperFile::parse(&input).unwrap();
+        test.write_file("out.opml", &tpf.to_opml());
+        test.assert_eq_to_golden("src/tests/export/opml_out.opml", "out.opml");
+    }
+
+    #[test]
+    fn test_tasks_to_ical() {
+        let tpf = TaskpaperFile::parse(include_str!("tests/export/in.taskpaper")).unwrap();
+        let clock = crate::FixedClock(chrono::NaiveDate::from_ymd_opt(2024, 4, 1).unwrap());
+        let ical = tasks_to_ical(&tpf, "@due", &clock).unwrap();
+        assert_eq!(ical, tasks_to_ical(&tpf, "@due", &clock).unwrap());
+        let lines = ical
+            .split("\r\n")
+            .filter(|line| !line.starts_with("UID:"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "BEGIN:VCALENDAR",
+                "VERSION:2.0",
+                "PRODID:-//SirVer//taskpaper//EN",
+                "BEGIN:VTODO",
+                "DTSTAMP:20240401T000000Z",
+                "SUMMARY:Mow the lawn",
+                "DTSTART;VALUE=DATE:20240501",
+                "DUE;VALUE=DATE:20240501",
+                "CATEGORIES:Home,Garden",
+                "END:VTODO",
+                "END:VCALENDAR",
+                "",
+            ],
+            lines
+        );
+        assert!(ical.contains("\r\nUID:"));
+
+        let mut folded = String::new();
+        append_ical_line(&mut folded, &format!("SUMMARY:{}", "x".repeat(100)));
+        assert_eq!(
+            format!("SUMMARY:{}\r\n {}\r\n", "x".repeat(67), "x".repeat(33)),
+            folded
+        );
+    }
+
+    #[test]
+    fn test_search_to_csv() {
+        let tpf = TaskpaperFile::parse(
+            "Shopping:\n\t- Milk, eggs @store(Corner \"Shop\") @next\n\t- Bread @next\n",
+        )
+        .unwrap();
+        let csv = tpf
+            .search_to_csv("@next", &["line", "type", "text", "store", "next"])
+            .unwrap();
+        assert_eq!(
+            "line,type,text,store,next\r\n\
+             2,task,\"Milk, eggs\",\"Corner \"\"Shop\"\"\",\r\n\
+             3,task,Bread,,\r\n",
+            csv
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! The steps that keep the common files of a database in order after any file has changed.
+
+use crate::{
+    collapse_done, mirror_changes, Clock, CommonFileKind, Database, Error, FilterMode, Item,
+    ItemKind, MatchKey, NodeId, Position, Recurrence, Result, Tag, TaskpaperFile,
+};
+use std::collections::BTreeMap;
+
+/// The steps of housekeeping a database, each of which can be turned off. The default runs all
+/// of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Housekeeping {
+    /// Move items tagged '@tickle' from inbox and todo into the tickle file, and items whose
+    /// '@to_inbox' date has come from the tickle file into the inbox. See `tickle`.
+    pub tickle: bool,
+
+    /// Mirror changes made in the timeline back into todo, then write the timeline anew from the
+    /// due items in todo. See `extract_timeline`.
+    pub extract_timeline: bool,
+
+    /// Mirror changes made in the checkout back into todo, then write the checkout anew from the
+    /// next items in todo. Only runs if the database has a checkout file. See `extract_checkout`.
+    pub extract_checkout: bool,
+
+    /// Collapse the done items in todo if 'collapse_done' is set in the config.
+    pub collapse_done: bool,
+
+    /// Write todo in its configured format, even if no other step changed it.
+    pub format_todo: bool,
+
+    /// Write inbox in its configured format, even if no other step changed it.
+    pub format_inbox: bool,
+}
+
+impl Default for Housekeeping {
+    fn default() -> Self {
+        Housekeeping {
+            tickle: true,
+            extract_timeline: true,
+            extract_checkout: true,
+            collapse_done: true,
+            format_todo: true,
+            format_inbox: true,
+        }
+    }
+}
+
+impl Housekeeping {
+    /// Runs all enabled steps on the common files of 'db' and returns the files that changed on
+    /// disk.
+    pub fn run(&self, db: &Database) -> Result<Vec<CommonFileKind>> {
+        self.run_with_clock(db, &db.config()?.clock())
+    }
+
+    /// Like 'run', but takes today's date from 'clock'.
+    pub fn run_with_clock(&self, db: &Database, clock: &dyn Clock) -> Result<Vec<CommonFileKind>> {
+        let mut inbox = db.parse_common_file(CommonFileKind::Inbox)?;
+        let mut todo = db.parse_common_file(CommonFileKind::Todo)?;
+        let mut write_inbox = self.format_inbox;
+        let mut write_todo = self.format_todo;
+        let mut changed = Vec::new();
+
+        let mut tickle_file = None;
+        if self.tickle {
+            let mut file = db.parse_common_file(CommonFileKind::Tickle)?;
+            tickle(&mut inbox, &mut todo, &mut file, clock)?;
+            tickle_file = Some(file);
+            write_inbox = true;
+            write_todo = true;
+        }
+        let mut timeline = None;
+        if self.extract_timeline {
+            timeline = Some(extract_timeline(db, &mut todo, clock)?);
+            write_todo = true;
+        }
+        let mut checkout = None;
+        if self.extract_checkout && db.path_of_common_file(CommonFileKind::Checkout)?.is_some() {
+            checkout = Some(extract_checkout(db, &mut todo)?);
+            write_todo = true;
+        }
+        if self.collapse_done {
+            if let Some(period) = db.config()?.collapse_done {
+                write_todo |= collapse_done(&mut todo, period, clock.today()) > 0;
+            }
+        }
+
+        // It is very important to first write todo, so that the extract methods that might be run
+        // now on file change do not run into an infinite loop.
+        let files = [
+            (write_todo.then_some(&todo), CommonFileKind::Todo),
+            (write_inbox.then_some(&inbox), CommonFileKind::Inbox),
+            (tickle_file.as_ref(), CommonFileKind::Tickle),
+            (timeline.as_ref(), CommonFileKind::Timeline),
+            (checkout.as_ref(), CommonFileKind::Checkout),
+        ];
+        for (tpf, kind) in files {
+            if let Some(tpf) = tpf {
+                if db.overwrite_common_file(tpf, kind)? {
+                    changed.push(kind);
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Moves items tagged '@tickle(date)' from 'inbox' and 'todo' into 'tickle', renaming the tag to
+/// '@to_inbox', and moves all items of 'tickle' with a '@to_inbox' date on or before today into
+/// 'inbox'. Items that also carry '@every', e.g. '@every(1w)' or '@every(weekly(mon))', stay in
+/// 'tickle' with '@to_inbox' set to their next day after today, see `Recurrence`.
+pub fn tickle(
+    inbox: &mut TaskpaperFile,
+    todo: &mut TaskpaperFile,
+    tickle: &mut TaskpaperFile,
+    clock: &dyn Clock,
+) -> Result<()> {
+    let today = clock.today();
+    let mut node_ids = Vec::new();
+    for node_id in inbox.filter("@tickle", FilterMode::PruneSubtree)? {
+        node_ids.push(tickle.copy_node(inbox, &node_id));
+    }
+    for node_id in todo.filter("@tickle", FilterMode::PruneSubtree)? {
+        node_ids.push(tickle.copy_node(todo, &node_id));
+    }
+
+    for node_id in node_ids {
+        let tags = tickle[&node_id].item_mut().tags_mut();
+        if tags.get_value("tickle").is_none() {
+            let text = tickle[&node_id].item().text().to_string();
+            return Err(Error::MissingTagValue("tickle".to_string(), text));
+        }
+        tags.rename("tickle", "to_inbox");
+        tickle.insert_node(node_id, Position::AsLast)?;
+    }
+    tickle.sort_nodes_by_tag_date("to_inbox");
+
+    let to_inbox = tickle.filter(
+        &format!("@to_inbox <= \"{}\"", today.format("%Y-%m-%d")),
+        FilterMode::PruneSubtree,
+    )?;
+    for node_id in to_inbox {
+        // Invalid recurrences, e.g. '@every(0d)', fail before anything is moved, so that items do
+        // not pile up in the inbox.
+        let next = match tickle[&node_id].item().tags().get_value("every") {
+            Some(every) => Some(Recurrence::parse(every)?.next_after(today)?),
+            None => None,
+        };
+        let inbox_id = inbox.copy_node(tickle, &node_id);
+        inbox.insert_node(inbox_id.clone(), Position::AsLast)?;
+
+        let next = match next {
+            Some(next) => next,
+            None => continue,
+        };
+        let again_id = tickle.copy_node(inbox, &inbox_id);
+        let to_inbox = next.format("%Y-%m-%d").to_string();
+        tickle[&again_id]
+            .item_mut()
+            .tags_mut()
+            .insert(Tag::new("to_inbox".to_string(), Some(to_inbox)));
+        tickle.insert_node(again_id, Position::AsLast)?;
+    }
+    tickle.sort_nodes_by_tag_date("to_inbox");
+    Ok(())
+}
+
+/// Mirrors changes in the timeline file of 'db' back into 'todo' and returns a new timeline with
+/// all items of 'todo' that are due and not done, grouped into one project per day.
+pub fn extract_timeline(
+    db: &Database,
+    todo: &mut TaskpaperFile,
+    clock: &dyn Clock,
+) -> Result<TaskpaperFile> {
+    if let Some(path) = db.path_of_common_file(CommonFileKind::Timeline)? {
+        mirror_changes(&path, todo, MatchKey::Text)?;
+    }
+    let today = clock.today();
+    let mut timeline = TaskpaperFile::new();
+    let node_ids = todo.search("@due and not @done")?;
+    let mut sorted = BTreeMap::new();
+    for node_id in &node_ids {
+        let item = todo[node_id].item();
+
+        let due = match item.tags().get_value("due") {
+            None => continue,
+            Some(v) => v,
+        };
+        let mut due = item
+            .tags()
+            .value_as_date("due")
+            .ok_or_else(|| Error::InvalidDate(due.to_string()))?;
+        if due < today {
+            due = today.pred_opt().unwrap();
+        }
+        sorted.entry(due).or_insert_with(Vec::new).push(item);
+    }
+
+    for (due, due_items) in sorted {
+        let diff_days = due.signed_duration_since(today).num_days();
+        let title = match diff_days {
+            0 => "Today".to_string(),
+            t if t < 0 => "Overdue".to_string(),
+            _ => format!(
+                "{} (+{} day{})",
+                due.format("%A, %d. %B %Y"),
+                diff_days,
+                if diff_days != 1 { "s" } else { "" }
+            ),
+        };
+
+        let project_id = timeline.insert(Item::new(ItemKind::Project, title), Position::AsLast);
+        for item in due_items {
+            // We do not copy over any notes here, just the item itself.
+            timeline.insert(item.clone(), Position::AsLastChildOf(&project_id));
+        }
+    }
+    Ok(timeline)
+}
+
+/// Mirrors changes in the checkout file of 'db' back into 'todo' and returns a new checkout with
+/// all items of 'todo' that are tagged '@next' and not done, together with their notes. The items
+/// are grouped under a project named like the project they are in.
+pub fn extract_checkout(db: &Database, todo: &mut TaskpaperFile) -> Result<TaskpaperFile> {
+    if let Some(path) = db.path_of_common_file(CommonFileKind::Checkout)? {
+        mirror_changes(&path, todo, MatchKey::Text)?;
+    }
+    let mut projects: Vec<(Option<&str>, Vec<NodeId>)> = Vec::new();
+    for node_id in todo.search("@next and not @done")? {
+        let project = todo
+            .ancestors(&node_id)
+            .find(|node| node.item().is_project())
+            .map(|node| todo[node.id()].item().text());
+        match projects.iter_mut().find(|(p, _)| *p == project) {
+            Some((_, node_ids)) => node_ids.push(node_id),
+            None => projects.push((project, vec![node_id])),
+        }
+    }
+
+    let mut checkout = TaskpaperFile::new();
+    for (project, node_ids) in projects {
+        let project_id = project.map(|title| {
+            checkout.insert(
+                Item::new(ItemKind::Project, title.to_string()),
+                Position::AsLast,
+            )
+        });
+        for node_id in node_ids {
+            let id = checkout.copy_node_filtered(todo, &node_id, |item| item.is_note());
+            let position = match &project_id {
+                Some(project_id) => Position::AsLastChildOf(project_id),
+                None => Position::AsLast,
+            };
+            checkout.insert_node(id, position)?;
+        }
+    }
+    Ok(checkout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use crate::{FixedClock, FormatOptions};
+
+    #[test]
+    fn test_tickle_every() {
+        let mut inbox = TaskpaperFile::new();
+        let mut todo =
+            TaskpaperFile::parse("- Water plants @every(1w) @tickle(2024-03-01)\n").unwrap();
+        let mut tickle_file = TaskpaperFile::parse(
+            "- Call mom @to_inbox(2024-03-01)\n- Later @to_inbox(2024-04-01)\n",
+        )
+        .unwrap();
+        let clock = FixedClock(chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap());
+        tickle(&mut inbox, &mut todo, &mut tickle_file, &clock).unwrap();
+
+        let options = FormatOptions::default();
+        assert_eq!(
+            "- Call mom @to_inbox(2024-03-01)\n- Water plants @every(1w) @to_inbox(2024-03-01)\n",
+            inbox.to_string(options)
+        );
+        assert_eq!("", todo.to_string(options));
+        assert_eq!(
+            "- Water plants @every(1w) @to_inbox(2024-03-08)\n- Later @to_inbox(2024-04-01)\n",
+            tickle_file.to_string(options)
+        );
+    }
+
+    #[test]
+    fn test_tickle_rejects_every_that_does_not_move_forward() {
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        for every in ["0d", "-1w"] {
+            let mut inbox = TaskpaperFile::new();
+            let mut todo = TaskpaperFile::new();
+            let mut tickle_file =
+                TaskpaperFile::parse(&format!("- W @every({}) @to_inbox(2024-03-01)\n", every))
+                    .unwrap();
+            assert!(matches!(
+                tickle(&mut inbox, &mut todo, &mut tickle_file, &FixedClock(today)),
+                Err(Error::InvalidDuration(_))
+            ));
+            assert_eq!("", inbox.to_string(FormatOptions::default()));
+        }
+    }
+
+    #[test]
+    fn test_extract_timeline() {
+        let test = DatabaseTest::new();
+        let config = test.write_config("");
+        let db = Database::from_dir(config.parent().unwrap()).unwrap();
+        let mut todo = TaskpaperFile::parse(
+            "- Tomorrow @due(2024-03-02)\n\
+             - Late @due(2024-02-20)\n\
+             - Now @due(2024-03-01)\n\
+             - Finished @due(2024-02-20) @done(2024-02-21)\n\
+             - Next week @due(2024-03-08)\n",
+        )
+        .unwrap();
+
+        let clock = FixedClock(chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap());
+        let timeline = extract_timeline(&db, &mut todo, &clock).unwrap();
+        let options = FormatOptions {
+            sort: crate::Sort::Nothing,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            "Overdue:\n\t- Late @due(2024-02-20)\n\n\
+             Today:\n\t- Now @due(2024-03-01)\n\n\
+             Saturday, 02. March 2024 (+1 day):\n\t- Tomorrow @due(2024-03-02)\n\n\
+             Friday, 08. March 2024 (+7 days):\n\t- Next week @due(2024-03-08)\n",
+            timeline.to_string(options)
+        );
+    }
+
+    #[test]
+    fn test_extract_checkout() {
+        let test = DatabaseTest::new();
+        let config = test.write_config("");
+        let db = Database::from_dir(config.parent().unwrap()).unwrap();
+        let mut todo = TaskpaperFile::parse(
+            "- Loose @next\n\
+             Work:\n\
+             \t- Report @next\n\
+             \t\tDraft is in the wiki\n\
+             \t\t- Collect numbers\n\
+             \t- Later\n\
+             \t- Shipped @next @done(2024-02-01)\n\
+             Home:\n\
+             \tGarden:\n\
+             \t\t- Water plants @next\n",
+        )
+        .unwrap();
+
+        let checkout = extract_checkout(&db, &mut todo).unwrap();
+        let options = FormatOptions {
+            sort: crate::Sort::Nothing,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            "- Loose @next\n\
+             Work:\n\
+             \t- Report @next\n\
+             \t\tDraft is in the wiki\n\n\
+             Garden:\n\
+             \t- Water plants @next\n",
+            checkout.to_string(options)
+        );
+    }
+
+    #[test]
+    fn test_housekeeping_checkout() {
+        let test = DatabaseTest::new();
+        let config = test.write_config("");
+        test.write_file(
+            CommonFileKind::Todo.to_path_buf(),
+            "Project:\n\t- Soon @next\n",
+        );
+        test.write_file(CommonFileKind::Inbox.to_path_buf(), "");
+        let db = Database::from_dir(config.parent().unwrap()).unwrap();
+        let steps = Housekeeping {
+            tickle: false,
+            extract_timeline: false,
+            ..Housekeeping::default()
+        };
+
+        // Without a checkout file there is nothing to extract into.
+        assert!(steps.run(&db).unwrap().is_empty());
+        assert_eq!(
+            None,
+            db.path_of_common_file(CommonFileKind::Checkout).unwrap()
+        );
+
+        test.write_file(CommonFileKind::Checkout.to_path_buf(), "");
+        assert_eq!(vec![CommonFileKind::Checkout], steps.run(&db).unwrap());
+        assert_eq!(
+            "Project:\n\t- Soon @next\n",
+            test.read_file(CommonFileKind::Checkout.to_path_buf())
+        );
+    }
+
+    #[test]
+    fn test_housekeeping() {
+        let test = DatabaseTest::new();
+        let config = test.write_config("");
+        test.write_file(
+            CommonFileKind::Inbox.to_path_buf(),
+            "- Later @tickle(2000-01-01)\n- Keep    @next\n",
+        );
+        test.write_file(
+            CommonFileKind::Todo.to_path_buf(),
+            "Project:\n\t- Due @due(2000-01-02)\n",
+        );
+        test.write_file(CommonFileKind::Tickle.to_path_buf(), "");
+
+        let db = Database::from_dir(config.parent().unwrap()).unwrap();
+        let steps = Housekeeping {
+            extract_timeline: false,
+            format_todo: false,
+            ..Housekeeping::default()
+        };
+        assert_eq!(vec![CommonFileKind::Inbox], steps.run(&db).unwrap());
+        // The tickled item went through the tickle file straight back into the inbox.
+        assert_eq!(
+            "- Keep @next\n- Later @to_inbox(2000-01-01)\n",
+            test.read_file(CommonFileKind::Inbox.to_path_buf())
+        );
+        assert_eq!("", test.read_file(CommonFileKind::Tickle.to_path_buf()));
+        assert_eq!(
+            None,
+            db.path_of_common_file(CommonFileKind::Timeline).unwrap()
+        );
+
+        assert_eq!(
+            vec![CommonFileKind::Timeline],
+            Housekeeping::default().run(&db).unwrap()
+        );
+        let timeline = db.parse_common_file(CommonFileKind::Timeline).unwrap();
+        assert_eq!(
+            "Overdue:\n\t- Due @due(2000-01-02)\n",
+            timeline.to_string(FormatOptions::default())
+        );
+        assert!(Housekeeping::default().run(&db).unwrap().is_empty());
+    }
+}
@@ -1,4 +1,7 @@
+mod clock;
 pub mod db;
+mod export;
+mod housekeeping;
 pub mod search;
 pub mod tag;
 
@@ -7,11 +10,14 @@ pub mod tag;
 pub mod testing;
 
 pub use crate::tag::{Tag, Tags};
+pub use clock::{Clock, FixedClock, SystemClock};
 pub use db::{CommonFileKind, Database};
+pub use export::tasks_to_ical;
+pub use housekeeping::{extract_checkout, extract_timeline, tickle, Housekeeping};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Write};
 use std::io;
@@ -21,9 +27,53 @@ use std::ops::{Index, IndexMut};
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FormatOptions {
     pub sort: Sort,
     pub empty_line_after_project: EmptyLineAfterProject,
+
+    /// If false, the line terminator of the last line is not written.
+    pub trailing_newline: bool,
+
+    /// If true, tags that were parsed and not changed since are written exactly as they were in
+    /// the source, e.g. '@foo()' is not turned into '@foo'. Unless 'tag_order' is set, tags are
+    /// then also written in the order of the source.
+    pub verbatim_tags: bool,
+
+    /// The order in which tags are written. If not set, tags of tasks are written
+    /// `TagOrder::ValuelessFirst` and tags of projects `TagOrder::Alphabetical`.
+    pub tag_order: Option<TagOrder>,
+
+    /// If set, notes longer than this many characters, not counting the indentation, are wrapped
+    /// into multiple lines with the same indentation.
+    pub wrap_notes: Option<usize>,
+}
+
+impl FormatOptions {
+    /// Returns these options changed so that tags are written exactly as they were parsed and in
+    /// the same order, i.e. formatting only touches indentation, empty lines and item order.
+    pub fn with_tags_as_written(self) -> Self {
+        FormatOptions {
+            verbatim_tags: true,
+            tag_order: Some(TagOrder::AsWritten),
+            ..self
+        }
+    }
+
+    /// Returns these options changed so that the output does not depend on the order of items and
+    /// tags in the file. See `Sort::Canonical`.
+    pub fn canonical(self) -> Self {
+        let tag_order = match self.tag_order {
+            Some(TagOrder::AsWritten) => None,
+            other => other,
+        };
+        FormatOptions {
+            sort: Sort::Canonical,
+            verbatim_tags: false,
+            tag_order,
+            ..self
+        }
+    }
 }
 
 impl Default for FormatOptions {
@@ -35,6 +85,68 @@ impl Default for FormatOptions {
                 first_level: 1,
                 others: 0,
             },
+            trailing_newline: true,
+            verbatim_tags: false,
+            tag_order: None,
+            wrap_notes: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// If true, lines the parser does not fully understand are kept as `ItemKind::RawLine` items
+    /// and written back byte by byte, instead of being turned into notes and reformatted.
+    pub preserve_raw: bool,
+
+    /// If false, whitespace at the start and end of the text of tasks and notes is kept. Only the
+    /// indentation, the leading '- ' of tasks and the tags, including the whitespace in front of
+    /// them, are removed.
+    pub trim_text: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            preserve_raw: false,
+            trim_text: true,
+        }
+    }
+}
+
+/// Something 'TaskpaperFile::parse_with_warnings' accepted, but that is likely a mistake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseWarningKind {
+    /// The line is indented more than one level deeper than the line before it.
+    IndentJump { from: u32, to: u32 },
+
+    /// The indentation of the line contains other whitespace than tabs.
+    MixedIndentation,
+
+    /// A tag value is opened with '(' but never closed.
+    UnterminatedTag,
+
+    /// A task or project without any text.
+    EmptyText,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// The index of the offending line in the input, starting at 0 like `Item::line_index`.
+    pub line_index: usize,
+    pub kind: ParseWarningKind,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line_index + 1)?;
+        match &self.kind {
+            ParseWarningKind::IndentJump { from, to } => {
+                write!(f, "indentation jumps from {} to {}", from, to)
+            }
+            ParseWarningKind::MixedIndentation => write!(f, "indentation is not only tabs"),
+            ParseWarningKind::UnterminatedTag => write!(f, "tag value is never closed"),
+            ParseWarningKind::EmptyText => write!(f, "item has no text"),
         }
     }
 }
@@ -50,9 +162,59 @@ pub struct Config {
     pub formats: HashMap<String, FormatOptions>,
     pub aliases: HashMap<String, String>,
     pub search: SearchOptions,
+
+    /// Overrides the file names of the common files, keyed by the name of the `CommonFileKind`,
+    /// e.g. 'inbox' or 'todo'. Paths are relative to the database root.
+    #[serde(default)]
+    pub common_files: HashMap<String, PathBuf>,
+
+    /// Glob patterns of directories that 'Database::parse_all_files' does not descend into, e.g.
+    /// '.git' or 'archive/*'. A pattern matches either the path of the directory relative to the
+    /// database root or its name.
+    #[serde(default)]
+    pub excluded_dirs: Vec<String>,
+
+    /// Templates for 'to_inbox --template', keyed by name. They can contain '{placeholder}'
+    /// slots that are filled in from the command line.
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+
+    /// If set, the command line client warns after every command if the inbox contains more tasks
+    /// than this.
+    #[serde(default)]
+    pub inbox_warn_threshold: Option<usize>,
+
+    /// If set, housekeeping replaces done items in the todo file with a note counting them per
+    /// project, instead of leaving them for 'log_done'. See `collapse_done`.
+    #[serde(default)]
+    pub collapse_done: Option<DonePeriod>,
+
+    /// The timezone that decides which day it is when missing '@done' or '@to_inbox' dates are
+    /// filled in, e.g. 'Europe/Berlin'. The local timezone of the machine if not set.
+    #[serde(default)]
+    pub timezone: Option<chrono_tz::Tz>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl Config {
+    /// The system clock in the configured 'timezone'.
+    pub fn clock(&self) -> SystemClock {
+        SystemClock {
+            timezone: self.timezone,
+        }
+    }
+
+    /// Today's date in the configured 'timezone'.
+    pub fn today(&self) -> chrono::NaiveDate {
+        self.clock().today()
+    }
+
+    /// The date in the configured 'timezone' at the moment 'now'.
+    pub fn today_at(&self, now: chrono::DateTime<chrono::Utc>) -> chrono::NaiveDate {
+        self.clock().date_at(now)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct NodeId(usize);
 
 impl NodeId {
@@ -72,6 +234,9 @@ pub struct Node {
     parent: Option<NodeId>,
     children: Vec<NodeId>,
     item: Item,
+
+    /// The whitespace in front of the item in the file it was parsed from.
+    leading_whitespace: Option<String>,
 }
 
 impl Node {
@@ -90,6 +255,18 @@ impl Node {
     pub fn children(&self) -> &[NodeId] {
         &self.children
     }
+
+    pub fn has_children(&self) -> bool {
+        !self.children.is_empty()
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    pub fn child_count(&self) -> usize {
+        self.children.len()
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -102,6 +279,46 @@ pub enum Error {
 
     #[error("invalid .config.toml: {0}")]
     InvalidConfig(String),
+
+    #[error("database directory not found: {}", .0.display())]
+    DatabaseNotFound(PathBuf),
+
+    #[error("path is not inside the database: {}", .0.display())]
+    NotInDatabase(PathBuf),
+
+    #[error("cannot move a node into its own subtree")]
+    MoveIntoSubtree,
+
+    #[error("nodes do not share a parent")]
+    NotSiblings,
+
+    #[error("corrupt tree: {0}")]
+    CorruptTree(String),
+
+    #[error("invalid tag name: '{0}'")]
+    InvalidTagName(String),
+
+    #[error("no item matches '{0}'")]
+    NoMatch(String),
+
+    #[error("{1} items match '{0}', expected exactly one")]
+    AmbiguousMatch(String, usize),
+
+    #[error("invalid duration: '{0}'")]
+    InvalidDuration(String),
+
+    #[error("invalid date: '{0}'")]
+    InvalidDate(String),
+
+    #[error("@{0} without value: '{1}'")]
+    MissingTagValue(String, String),
+
+    #[error("line {}: indentation jumps from {from} to {to}", .line_index + 1)]
+    IndentJump {
+        line_index: usize,
+        from: u32,
+        to: u32,
+    },
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -117,6 +334,115 @@ pub fn sanitize_item_text(text: &str) -> String {
         .to_string()
 }
 
+/// Parses durations like '3d', '2w', '1m' or '4y'. A month is 30 days, a year 365 days. Durations
+/// must be positive and small enough to be represented.
+pub fn parse_duration(s: &str) -> Result<chrono::Duration> {
+    let invalid = || Error::InvalidDuration(s.to_string());
+    let s = s.trim();
+    let unit = s.chars().last().ok_or_else(invalid)?;
+    let num: i64 = s[..s.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let days = match unit {
+        'd' => 1,
+        'w' => 7,
+        'm' => 30,
+        'y' => 365,
+        _ => return Err(invalid()),
+    };
+    if num <= 0 {
+        return Err(invalid());
+    }
+    num.checked_mul(days)
+        .and_then(chrono::Duration::try_days)
+        .ok_or_else(invalid)
+}
+
+/// When a repeating item comes back, as given in the value of '@repeat' or '@every'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recurrence {
+    // A fixed offset like '3d' or '2w', see `parse_duration`.
+    After(chrono::Duration),
+
+    // 'weekly(mon)': the next Monday.
+    Weekly(chrono::Weekday),
+
+    // 'monthly(1)': the next first of a month. Months that are too short use their last day.
+    Monthly(u32),
+}
+
+impl Recurrence {
+    /// Parses 'weekly(<weekday>)', 'monthly(<day>)' or a duration like '2w'.
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidDuration(s.to_string());
+        let s = s.trim();
+        let anchored = |prefix: &str| {
+            s.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_prefix('('))
+                .and_then(|rest| rest.strip_suffix(')'))
+                .map(str::trim)
+        };
+        if let Some(weekday) = anchored("weekly") {
+            return Ok(Recurrence::Weekly(weekday.parse().map_err(|_| invalid())?));
+        }
+        if let Some(day) = anchored("monthly") {
+            return match day.parse() {
+                Ok(day @ 1..=31) => Ok(Recurrence::Monthly(day)),
+                _ => Err(invalid()),
+            };
+        }
+        Ok(Recurrence::After(parse_duration(s)?))
+    }
+
+    /// Returns the first day after 'date' on which the item comes back, which is always later
+    /// than 'date'. Returns `Error::InvalidDuration` for an `After` that is not positive and
+    /// `Error::InvalidDate` if the day cannot be represented.
+    pub fn next_after(&self, date: chrono::NaiveDate) -> Result<chrono::NaiveDate> {
+        use chrono::Datelike;
+
+        let out_of_range = || Error::InvalidDate(date.to_string());
+        match *self {
+            Recurrence::After(duration) => {
+                if duration <= chrono::Duration::zero() {
+                    return Err(Error::InvalidDuration(format!("{}d", duration.num_days())));
+                }
+                date.checked_add_signed(duration).ok_or_else(out_of_range)
+            }
+            Recurrence::Weekly(weekday) => {
+                let (to, from) = (weekday.num_days_from_monday(), date.weekday());
+                let days = (to + 6 - from.num_days_from_monday()) % 7 + 1;
+                date.checked_add_days(chrono::Days::new(days.into()))
+                    .ok_or_else(out_of_range)
+            }
+            Recurrence::Monthly(day) => {
+                let in_month = |year: i32, month: u32| {
+                    let first = chrono::NaiveDate::from_ymd_opt(year, month, 1)?;
+                    first.with_day(day).or_else(|| {
+                        first
+                            .checked_add_days(chrono::Days::new(31))?
+                            .with_day(1)?
+                            .pred_opt()
+                    })
+                };
+                let candidate = in_month(date.year(), date.month()).ok_or_else(out_of_range)?;
+                let next = if candidate > date {
+                    Some(candidate)
+                } else if date.month() == 12 {
+                    in_month(date.year() + 1, 1)
+                } else {
+                    in_month(date.year(), date.month() + 1)
+                };
+                next.ok_or_else(out_of_range)
+            }
+        }
+    }
+}
+
+/// Returns a random version 4 UUID, e.g. '1b4e28ba-2fa1-4d2e-883f-0016d3cca427'.
+fn new_uuid() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Sort {
     // Do not change ordering of the items, print them as they arrive.
@@ -124,6 +450,62 @@ pub enum Sort {
 
     // Order projects on top, i.e. before tasks.
     ProjectsFirst,
+
+    // Notes first in their order, then projects, then tasks, each ordered by text. Files that
+    // only differ in the order of their items are written the same.
+    Canonical,
+}
+
+/// The period over which `collapse_done` counts done items before starting over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DonePeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl DonePeriod {
+    /// Returns the first day of the period containing 'today'. Weeks start on Monday.
+    pub fn start(self, today: chrono::NaiveDate) -> chrono::NaiveDate {
+        use chrono::Datelike;
+        match self {
+            DonePeriod::Day => today,
+            DonePeriod::Week => {
+                today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64)
+            }
+            DonePeriod::Month => today.with_day(1).unwrap(),
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            DonePeriod::Day => "today",
+            DonePeriod::Week => "this week",
+            DonePeriod::Month => "this month",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TagOrder {
+    // Keep the tags in the order they were written or inserted.
+    AsWritten,
+
+    // Order tags by name.
+    Alphabetical,
+
+    // Tags without value first, then tags with value, each ordered by name.
+    ValuelessFirst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportStyle {
+    // Regular taskpaper, the same as `TaskpaperFile::to_string`.
+    Taskpaper,
+
+    // A plain text outline: projects without the trailing ':' and tasks without the leading '- '.
+    // This cannot be parsed as taskpaper again.
+    Outline,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -134,33 +516,93 @@ pub struct EmptyLineAfterProject {
     pub others: usize,
 }
 
-fn append_project_to_string(item: &Item, buf: &mut String, indent: usize) -> fmt::Result {
-    let indent_str = "\t".repeat(indent);
-    let mut tags = item.tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
-    tags.sort();
-    let tags_string = if tags.is_empty() {
-        "".to_string()
+fn tag_to_string(tag: &Tag, raw: Option<&str>, options: FormatOptions) -> String {
+    match raw {
+        Some(raw) if options.verbatim_tags => raw.to_string(),
+        _ => tag.to_string(),
+    }
+}
+
+/// Returns the tags of 'item' together with their verbatim form, ordered by 'order'.
+fn ordered_tags(item: &Item, order: TagOrder) -> Vec<(Tag, Option<&str>)> {
+    let mut tags = item.tags.iter_with_raw().collect::<Vec<_>>();
+    match order {
+        TagOrder::AsWritten => (),
+        TagOrder::Alphabetical => {
+            tags.sort_by(|(a, _), (b, _)| (&a.name, &a.value).cmp(&(&b.name, &b.value)))
+        }
+        TagOrder::ValuelessFirst => tags.sort_by_key(|(t, _)| (t.value.is_some(), t.name.clone())),
+    }
+    tags
+}
+
+/// Returns the tags of 'item' ordered according to 'options', with a leading space, or an empty
+/// string if there are no tags.
+fn tags_to_string(item: &Item, default_order: TagOrder, options: FormatOptions) -> String {
+    let default_order = if options.verbatim_tags {
+        TagOrder::AsWritten
     } else {
-        format!(" {}", tags.join(" "))
+        default_order
     };
-    writeln!(buf, "{}{}:{}", indent_str, item.text, tags_string)?;
+    let tags = ordered_tags(item, options.tag_order.unwrap_or(default_order));
+    if tags.is_empty() {
+        return "".to_string();
+    }
+    let tag_strings = tags
+        .iter()
+        .map(|(t, raw)| tag_to_string(t, *raw, options))
+        .collect::<Vec<String>>();
+    format!(" {}", tag_strings.join(" "))
+}
 
+/// Appends 'item' formatted by `Item::to_line` to 'buf', every line prefixed by 'indent_str'. Raw
+/// lines are written as they are.
+fn append_item_to_string(
+    item: &Item,
+    buf: &mut impl Write,
+    indent_str: &str,
+    options: FormatOptions,
+) -> fmt::Result {
+    if item.kind == ItemKind::RawLine {
+        return writeln!(buf, "{}", item.text);
+    }
+    for line in item.to_line(&options).split('\n') {
+        writeln!(buf, "{}{}", indent_str, line)?;
+    }
     Ok(())
 }
 
-fn append_note_to_string(item: &Item, buf: &mut String, indent: usize) -> fmt::Result {
-    let indent = "\t".repeat(indent);
-    for line in item.text.split_terminator('\n') {
-        writeln!(buf, "{}{}", indent, line)?;
-    }
+fn append_outline_item_to_string(
+    item: &Item,
+    buf: &mut impl Write,
+    indent_str: &str,
+    default_order: TagOrder,
+    options: FormatOptions,
+) -> fmt::Result {
+    let tags_string = tags_to_string(item, default_order, options);
+    writeln!(buf, "{}{}{}", indent_str, item.text, tags_string)?;
     Ok(())
 }
 
+/// The state of a checkbox at the start of an item text, like '[ ] Milk' or '[X] Bread'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checkbox {
+    // '[ ]' or '[_]'.
+    Unchecked,
+
+    // '[x]' or '[X]'.
+    Checked,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ItemKind {
     Project,
     Task,
     Note,
+
+    /// A line that was not understood by the parser. The text is the complete original line,
+    /// including its indentation, and is written back unchanged.
+    RawLine,
 }
 
 // TODO(sirver): The goal should be to keep the contents of files unchanged as much as possible.
@@ -173,8 +615,8 @@ pub struct Item {
     /// that this text does not neither contain a newline '\n' or a carriage return '\r' character.
     pub text: String,
 
-    /// The collection of Tags that this item contains. Order of the tags is currently lost,
-    /// they will be reordered on write.
+    /// The collection of Tags that this item contains. The order of the tags is kept, but they
+    /// are reordered on write according to `FormatOptions::tag_order`.
     pub tags: Tags,
     line_index: Option<usize>,
 
@@ -211,6 +653,50 @@ impl Item {
         item.tags = tags;
         item
     }
+
+    /// Starts building a task, e.g. `Item::task("Buy milk").with_tag("next", None).build()`.
+    pub fn task(text: impl Into<String>) -> ItemBuilder {
+        ItemBuilder::new(ItemKind::Task, text.into())
+    }
+
+    /// Starts building a project, see 'task'.
+    pub fn project(text: impl Into<String>) -> ItemBuilder {
+        ItemBuilder::new(ItemKind::Project, text.into())
+    }
+
+    /// Starts building a note, see 'task'.
+    pub fn note(text: impl Into<String>) -> ItemBuilder {
+        ItemBuilder::new(ItemKind::Note, text.into())
+    }
+}
+
+/// Builds an `Item` with tags, created by `Item::task`, `Item::project` or `Item::note`.
+#[derive(Debug, Clone)]
+pub struct ItemBuilder {
+    kind: ItemKind,
+    text: String,
+    tags: Tags,
+}
+
+impl ItemBuilder {
+    fn new(kind: ItemKind, text: String) -> Self {
+        ItemBuilder {
+            kind,
+            text,
+            tags: Tags::new(),
+        }
+    }
+
+    /// Adds the tag '@name' or '@name(value)', replacing an earlier tag with the same name.
+    pub fn with_tag(mut self, name: &str, value: Option<&str>) -> Self {
+        self.tags
+            .insert(Tag::new(name.to_string(), value.map(|v| v.to_string())));
+        self
+    }
+
+    pub fn build(self) -> Item {
+        Item::new_with_tags(self.kind, self.text, self.tags)
+    }
 }
 
 impl Item {
@@ -235,6 +721,12 @@ impl Item {
         }
     }
 
+    /// Parses a single line of a taskpaper file into an item, the same way `TaskpaperFile::parse`
+    /// does. The indentation is the number of leading tabs. Empty lines become empty notes.
+    pub fn parse_line(line: &str) -> Item {
+        line_to_item(line, ParseOptions::default())
+    }
+
     pub fn line_index(&self) -> Option<usize> {
         // TODO(sirver): return by ref
         self.line_index
@@ -244,6 +736,55 @@ impl Item {
         &self.text
     }
 
+    /// Returns the state of the checkbox the text starts with, if any.
+    pub fn checkbox(&self) -> Option<Checkbox> {
+        match self.text.get(..3)? {
+            "[ ]" | "[_]" => Some(Checkbox::Unchecked),
+            "[x]" | "[X]" => Some(Checkbox::Checked),
+            _ => None,
+        }
+    }
+
+    /// Sets the state of the checkbox at the start of the text, adding it if there is none, or
+    /// removes it if 'checkbox' is None. An existing box keeps its style, i.e. '[ ]' toggles with
+    /// '[x]' and '[_]' with '[X]'. New boxes use the latter.
+    pub fn set_checkbox(&mut self, checkbox: Option<Checkbox>) {
+        let current = self.text.get(..3).filter(|_| self.checkbox().is_some());
+        let lowercase = matches!(current, Some("[ ]") | Some("[x]"));
+        let marker = match (checkbox, lowercase) {
+            (None, _) => None,
+            (Some(Checkbox::Unchecked), true) => Some("[ ]"),
+            (Some(Checkbox::Checked), true) => Some("[x]"),
+            (Some(Checkbox::Unchecked), false) => Some("[_]"),
+            (Some(Checkbox::Checked), false) => Some("[X]"),
+        };
+        let rest = match current {
+            Some(_) => self.text[3..].trim_start(),
+            None => &self.text,
+        };
+        self.text = match marker {
+            Some(marker) if rest.is_empty() => marker.to_string(),
+            Some(marker) => format!("{} {}", marker, rest),
+            None => rest.to_string(),
+        };
+    }
+
+    /// Returns the text of this item split on 'sep', with every segment trimmed and empty segments
+    /// dropped, joined by 'sep' surrounded by single spaces. This makes texts like "A•B" and
+    /// "A  •  B" compare equal. If 'sep' is only whitespace, the trimmed text is returned.
+    pub fn normalized_text(&self, sep: &str) -> String {
+        let sep = sep.trim();
+        if sep.is_empty() {
+            return self.text.trim().to_string();
+        }
+        self.text
+            .split(sep)
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(&format!(" {} ", sep))
+    }
+
     pub fn tags(&self) -> &Tags {
         &self.tags
     }
@@ -251,36 +792,140 @@ impl Item {
     pub fn tags_mut(&mut self) -> &mut Tags {
         &mut self.tags
     }
+
+    /// Formats this item as it is written in a taskpaper file, e.g. '- Task @next' or
+    /// 'Project:', without indentation, children and line terminator. Only a note that is wrapped
+    /// because of 'options.wrap_notes' spans more than one line.
+    pub fn to_line(&self, options: &FormatOptions) -> String {
+        match self.kind {
+            ItemKind::Project => format!(
+                "{}:{}",
+                self.text,
+                tags_to_string(self, TagOrder::Alphabetical, *options)
+            ),
+            ItemKind::Task => format!(
+                "- {}{}",
+                self.text,
+                tags_to_string(self, TagOrder::ValuelessFirst, *options)
+            ),
+            ItemKind::Note => match options.wrap_notes {
+                Some(width) => wrap_note(&self.text, width),
+                None => self.text.clone(),
+            },
+            ItemKind::RawLine => self.text.clone(),
+        }
+    }
 }
 
-fn append_task_to_string(item: &Item, buf: &mut String, indent: usize) -> fmt::Result {
-    let indent_str = "\t".repeat(indent);
-    let mut tags = item.tags.iter().collect::<Vec<Tag>>();
-    tags.sort_by_key(|t| (t.value.is_some(), t.name.clone()));
-    let tags_string = if tags.is_empty() {
-        "".to_string()
-    } else {
-        let tag_strings = tags.iter().map(|t| t.to_string()).collect::<Vec<String>>();
-        format!(" {}", tag_strings.join(" "))
+/// Wraps the note 'text' into lines of at most 'width' characters where possible. A line that
+/// would be parsed back as something else than a note, e.g. '- word' or 'words:', is joined with
+/// its neighbor, so that wrapping never changes the items of a file.
+fn wrap_note(text: &str, width: usize) -> String {
+    let options = ParseOptions {
+        preserve_raw: true,
+        ..ParseOptions::default()
     };
-    writeln!(buf, "{}- {}{}", indent_str, item.text, tags_string)?;
-    Ok(())
+    let is_note = |line: &str| line_to_item(line, options).is_note();
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut pending = String::new();
+    for part in textwrap::wrap(text, width) {
+        if !pending.is_empty() {
+            pending.push(' ');
+        }
+        pending.push_str(&part);
+        // Other lines, e.g. one ending like a project, are continued with the next part.
+        if !is_note(&pending) && !is_task(&pending) {
+            continue;
+        }
+        match lines.last_mut() {
+            // A line starting like a task is appended to the line before it.
+            Some(last) if !is_note(&pending) => {
+                last.push(' ');
+                last.push_str(&pending);
+            }
+            _ => lines.push(mem::take(&mut pending)),
+        }
+        pending.clear();
+    }
+    if !pending.is_empty() {
+        match lines.last_mut() {
+            Some(last) => {
+                last.push(' ');
+                last.push_str(&pending);
+            }
+            None => lines.push(pending),
+        }
+    }
+    if lines.iter().all(|line| is_note(line)) {
+        lines.join("\n")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Returns true if the content of 'path' differs from 'new' or the file cannot be read.
+fn has_changed(path: impl AsRef<Path>, new: &str) -> bool {
+    match std::fs::read_to_string(path) {
+        Err(_) => true,
+        Ok(old) => sha1_smol::Sha1::from(&old) != sha1_smol::Sha1::from(new),
+    }
+}
+
+/// Where 'print_nodes' writes to. Writers that want to know where each item ends up are told
+/// before it is written.
+trait ItemWriter: Write {
+    fn start_item(&mut self, _node_id: &NodeId) {}
+}
+
+impl ItemWriter for String {}
+
+impl ItemWriter for fmt::Formatter<'_> {}
+
+/// Discards the output, but remembers the index of the line every item starts on.
+#[derive(Default)]
+struct LineIndexWriter {
+    num_lines: usize,
+    line_indices: Vec<(NodeId, usize)>,
+}
+
+impl Write for LineIndexWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.num_lines += s.matches('\n').count();
+        Ok(())
+    }
+}
+
+impl ItemWriter for LineIndexWriter {
+    fn start_item(&mut self, node_id: &NodeId) {
+        self.line_indices.push((node_id.clone(), self.num_lines));
+    }
 }
 
-fn print_nodes(
+fn print_nodes<W: ItemWriter>(
     mut node_ids: Vec<NodeId>,
     arena: &[Node],
-    buf: &mut String,
+    buf: &mut W,
     indent: usize,
+    style: ExportStyle,
+    preserve_indentation: bool,
     options: FormatOptions,
 ) -> fmt::Result {
     // Projects are bubbled to the top.
     match options.sort {
         Sort::Nothing => (),
         Sort::ProjectsFirst => node_ids.sort_by_key(|id| !arena[id.0].item.is_project()),
+        Sort::Canonical => node_ids.sort_by_key(|id| {
+            let item = &arena[id.0].item;
+            match item.kind {
+                ItemKind::Note | ItemKind::RawLine => (0, ""),
+                ItemKind::Project => (1, item.text()),
+                ItemKind::Task => (2, item.text()),
+            }
+        }),
     }
 
-    let maybe_empty_line = |buf: &mut String, idx: usize| -> fmt::Result {
+    let maybe_empty_line = |buf: &mut W, idx: usize| -> fmt::Result {
         // Only if there is a next item and that is a project do we actually print a new line.
         if let Some(id) = node_ids.get(idx + 1) {
             if arena[id.0].item.is_project() {
@@ -290,28 +935,64 @@ fn print_nodes(
         Ok(())
     };
 
+    // Blank notes are written as empty lines, but never more in a row than we would add after a
+    // project on this level.
+    let max_blank_lines = match indent {
+        0 => options.empty_line_after_project.top_level,
+        1 => options.empty_line_after_project.first_level,
+        _ => options.empty_line_after_project.others,
+    };
+    let mut num_blank_lines = 0;
+
     for (idx, id) in node_ids.iter().enumerate() {
         let node = &arena[id.0];
-        let add_empty_line = match &node.item.kind {
-            ItemKind::Project => {
-                append_project_to_string(&node.item, buf, indent)?;
-                match indent {
-                    0 => options.empty_line_after_project.top_level,
-                    1 => options.empty_line_after_project.first_level,
-                    _ => options.empty_line_after_project.others,
-                }
-            }
-            ItemKind::Task => {
-                append_task_to_string(&node.item, buf, indent)?;
-                0
-            }
-            ItemKind::Note => {
-                append_note_to_string(&node.item, buf, indent)?;
-                0
+        if node.item.is_note() && node.item.text.trim().is_empty() && node.is_leaf() {
+            num_blank_lines += 1;
+            if num_blank_lines <= max_blank_lines {
+                buf.start_item(id);
+                writeln!(buf)?;
             }
+            continue;
+        }
+        num_blank_lines = 0;
+
+        let indent_str = match &node.leading_whitespace {
+            Some(whitespace) if preserve_indentation => whitespace.clone(),
+            _ => "\t".repeat(indent),
+        };
+        buf.start_item(id);
+        match (&node.item.kind, style) {
+            (ItemKind::Project, ExportStyle::Outline) => append_outline_item_to_string(
+                &node.item,
+                buf,
+                &indent_str,
+                TagOrder::Alphabetical,
+                options,
+            )?,
+            (ItemKind::Task, ExportStyle::Outline) => append_outline_item_to_string(
+                &node.item,
+                buf,
+                &indent_str,
+                TagOrder::ValuelessFirst,
+                options,
+            )?,
+            _ => append_item_to_string(&node.item, buf, &indent_str, options)?,
+        }
+        let add_empty_line = if node.item.is_project() {
+            max_blank_lines
+        } else {
+            0
         };
 
-        print_nodes(node.children.clone(), arena, buf, indent + 1, options)?;
+        print_nodes(
+            node.children.clone(),
+            arena,
+            buf,
+            indent + 1,
+            style,
+            preserve_indentation,
+            options,
+        )?;
 
         for _ in 0..add_empty_line {
             maybe_empty_line(buf, idx)?;
@@ -327,6 +1008,78 @@ enum LineKind {
     Task,
     Project,
     Note,
+    Raw,
+}
+
+/// Splits the front matter, a block of TOML delimited by '---' lines at the very start of 'input',
+/// from the rest. Returns the content of the block and its parsed values, the remaining input and
+/// the number of lines the block spans. A block that is not valid TOML is not front matter, so
+/// files that merely start with a '---' separator are parsed as before.
+fn split_front_matter(input: &str) -> (Option<(&str, toml::value::Table)>, &str, usize) {
+    let mut content_start = None;
+    let mut offset = 0;
+    for (line_index, line) in input.split_inclusive('\n').enumerate() {
+        let line_start = offset;
+        offset += line.len();
+        if line.trim_end() != "---" {
+            if content_start.is_none() {
+                break;
+            }
+            continue;
+        }
+        match content_start {
+            None => content_start = Some(offset),
+            Some(start) => {
+                let front_matter = &input[start..line_start];
+                return match toml::from_str(front_matter) {
+                    Ok(table) => (
+                        Some((front_matter, table)),
+                        &input[offset..],
+                        line_index + 1,
+                    ),
+                    Err(_) => (None, input, 0),
+                };
+            }
+        }
+    }
+    (None, input, 0)
+}
+
+/// Returns the non-empty lines of 'input' that become items together with their index, which is
+/// offset by 'line_offset'.
+fn content_lines(
+    input: &str,
+    line_offset: usize,
+    options: ParseOptions,
+) -> impl Iterator<Item = (usize, &str)> {
+    // TODO(sirver): Swift does not filter empty line and that feels more correct.
+    let is_newline = |c| c == '\n' || c == '\r';
+    let trimmed = if options.trim_text {
+        input.trim_start()
+    } else {
+        input.trim_start_matches(is_newline)
+    };
+    let skipped_lines = input[..input.len() - trimmed.len()].matches('\n').count();
+    let trimmed = if options.trim_text {
+        trimmed.trim_end()
+    } else {
+        trimmed.trim_end_matches(is_newline)
+    };
+    trimmed
+        .lines()
+        .enumerate()
+        .map(move |(line_index, line)| (line_index + line_offset + skipped_lines, line))
+        .filter(|(_line_index, line)| !line.trim().is_empty())
+}
+
+/// Returns true for lines that cannot be represented by our model without changing them: lines
+/// indented with anything but tabs, lines containing control characters and lines with a tag value
+/// that is never closed.
+fn is_unsupported(line: &str) -> bool {
+    let after_indent = line.trim_start_matches('\t');
+    after_indent.starts_with(char::is_whitespace)
+        || line.chars().any(|c| c.is_control() && c != '\t')
+        || tag::has_unterminated_value(line)
 }
 
 fn is_task(line: &str) -> bool {
@@ -351,9 +1104,14 @@ fn classify(without_tags: &str) -> LineKind {
     }
 }
 
-fn parse_task_text(line_without_tags: &str) -> String {
+fn parse_task_text(line_without_tags: &str, trim: bool) -> String {
     // Trim the leading '- '
-    line_without_tags.trim()[1..].trim_start().to_string()
+    let text = &line_without_tags.trim_start()[2..];
+    if trim {
+        text.trim().to_string()
+    } else {
+        text.to_string()
+    }
 }
 
 fn parse_project_text(line_without_tags: &str) -> String {
@@ -362,35 +1120,61 @@ fn parse_project_text(line_without_tags: &str) -> String {
     without_tags[..without_tags.len() - 1].to_string()
 }
 
-fn parse_item<'a>(
-    it: &mut Peekable<impl Iterator<Item = (usize, &'a str)>>,
-    arena: &mut Vec<Node>,
-) -> NodeId {
-    let (line_index, line) = it.next().unwrap();
-
-    let (without_tags, tags) = tag::extract_tags(line.to_string());
-    let without_tags = without_tags.trim();
+/// Turns a single 'line' into an item, classifying it by its shape and extracting its tags. The
+/// indentation is counted in tabs.
+fn line_to_item(line: &str, options: ParseOptions) -> Item {
+    let (untrimmed, mut tags) = tag::extract_tags(line.to_string());
+    let without_tags = untrimmed.trim();
 
-    let (kind, text): (_, Cow<str>) = match classify(&without_tags) {
-        LineKind::Task => (ItemKind::Task, Cow::Owned(parse_task_text(&without_tags))),
+    let line_kind = if options.preserve_raw && is_unsupported(line) {
+        LineKind::Raw
+    } else {
+        classify(&without_tags)
+    };
+    let (kind, text): (_, Cow<str>) = match line_kind {
+        LineKind::Task => (
+            ItemKind::Task,
+            Cow::Owned(parse_task_text(&untrimmed, options.trim_text)),
+        ),
         LineKind::Project => (
             ItemKind::Project,
             Cow::Owned(parse_project_text(&without_tags)),
         ),
-        LineKind::Note => (ItemKind::Note, Cow::Borrowed(without_tags)),
+        LineKind::Note if options.trim_text => (ItemKind::Note, Cow::Borrowed(without_tags)),
+        LineKind::Note => (
+            ItemKind::Note,
+            Cow::Borrowed(untrimmed.trim_start_matches('\t')),
+        ),
+        LineKind::Raw => {
+            tags = Tags::new();
+            (ItemKind::RawLine, Cow::Borrowed(line))
+        }
     };
 
-    let indent = find_indent(line);
+    Item {
+        indent: find_indent(line),
+        kind,
+        text: text.to_string(),
+        tags,
+        line_index: None,
+    }
+}
+
+fn parse_item<'a>(
+    it: &mut Peekable<impl Iterator<Item = (usize, &'a str)>>,
+    arena: &mut Vec<Node>,
+    options: ParseOptions,
+) -> NodeId {
+    let (line_index, line) = it.next().unwrap();
+
+    let mut item = line_to_item(line, options);
+    item.line_index = Some(line_index);
+    let indent = item.indent;
     arena.push(Node {
         parent: None,
         children: Vec::new(),
-        item: Item {
-            indent,
-            kind,
-            text: text.to_string(),
-            tags,
-            line_index: Some(line_index),
-        },
+        item,
+        leading_whitespace: Some(line[..line.len() - line.trim_start().len()].to_string()),
     });
     let node_id = NodeId(arena.len() - 1);
 
@@ -401,7 +1185,7 @@ fn parse_item<'a>(
             None => break,
             Some(_) => (),
         }
-        let child_node = parse_item(it, arena);
+        let child_node = parse_item(it, arena, options);
         arena[child_node.0].parent = Some(node_id.clone());
         children.push(child_node);
     }
@@ -410,13 +1194,88 @@ fn parse_item<'a>(
     node_id
 }
 
-#[derive(Debug)]
-pub struct TaskpaperFile {
+/// The nodes that are moved together when siblings are sorted: a task or project and the notes
+/// that follow it on the same level. Notes indented below a task are its children and move with it
+/// anyways, but notes on the same level only belong to it by position.
+struct SortUnit {
+    head: NodeId,
+    notes: Vec<NodeId>,
+}
+
+/// Sorts the siblings 'node_ids' as `SortUnit`s by the key 'f' returns for the head of each unit.
+/// Notes before the first task or project belong to the parent and stay in front.
+fn sort_units_by_key<K, F>(node_ids: Vec<NodeId>, arena: &[Node], mut f: F) -> Vec<NodeId>
+where
+    F: FnMut(&Node) -> K,
+    K: Ord,
+{
+    let mut leading_notes = Vec::new();
+    let mut units: Vec<SortUnit> = Vec::new();
+    for node_id in node_ids {
+        let is_note = arena[node_id.0].item.is_note();
+        match units.last_mut() {
+            Some(unit) if is_note => unit.notes.push(node_id),
+            None if is_note => leading_notes.push(node_id),
+            _ => units.push(SortUnit {
+                head: node_id,
+                notes: Vec::new(),
+            }),
+        }
+    }
+    units.sort_by_key(|unit| f(&arena[unit.head.0]));
+
+    let mut sorted = leading_notes;
+    for unit in units {
+        sorted.push(unit.head);
+        sorted.extend(unit.notes);
+    }
+    sorted
+}
+
+/// Evaluates 'expr' for the node with 'node_id', with '@child' expressions evaluated against its
+/// descendants.
+fn node_matches(arena: &[Node], node_id: &NodeId, expr: &search::Expr) -> bool {
+    fn any_descendant(arena: &[Node], node_id: &NodeId, expr: &search::Expr) -> bool {
+        arena[node_id.0].children.iter().any(|child_id| {
+            node_matches(arena, child_id, expr) || any_descendant(arena, child_id, expr)
+        })
+    }
+
+    expr.evaluate_with_descendants(&arena[node_id.0].item.tags, &|inner| {
+        any_descendant(arena, node_id, inner)
+    })
+    .is_truish()
+}
+
+/// Counts of the items in a `TaskpaperFile`, see `TaskpaperFile::summary`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FileSummary {
+    pub tasks: usize,
+    pub projects: usize,
+    pub notes: usize,
+
+    /// Items tagged with '@done'.
+    pub done: usize,
+
+    /// Items that are not done and have a '@due' date before today.
+    pub overdue: usize,
+
+    /// How many items carry each tag.
+    pub tags: BTreeMap<String, usize>,
+}
+
+#[derive(Debug)]
+pub struct TaskpaperFile {
     arena: Vec<Node>,
     nodes: Vec<NodeId>,
 
     /// If this was loaded from a file, this will be set to the path of that file.
     path: Option<PathBuf>,
+
+    /// The TOML front matter between the '---' lines at the start of the file, written back as
+    /// is, and the values parsed from it.
+    front_matter: Option<String>,
+    metadata: BTreeMap<String, String>,
 }
 
 impl AsRef<TaskpaperFile> for TaskpaperFile {
@@ -425,12 +1284,105 @@ impl AsRef<TaskpaperFile> for TaskpaperFile {
     }
 }
 
+/// What happens to the children of a node that is unlinked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildrenStrategy {
+    // The children are removed together with the node.
+    Remove,
+
+    // The children take the place of the node in its parent.
+    Reparent,
+
+    // The children become the last top level nodes.
+    Promote,
+}
+
+/// Which nodes 'TaskpaperFile::filter' tests against the query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    // A matching node is removed with all its children, which are not tested.
+    PruneSubtree,
+
+    // Every node is tested on its own. Only matching nodes are removed, their remaining children
+    // take their place in the parent.
+    TestAll,
+}
+
+/// A difference between two versions of a file, as returned by `TaskpaperFile::diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The item only exists in the new file, the id is from the new file.
+    Added(NodeId),
+
+    /// The item only exists in the old file, the id is from the old file.
+    Removed(NodeId),
+
+    /// The item exists in both, but its tags or notes are different.
+    Modified { old: NodeId, new: NodeId },
+}
+
+/// How `mirror_changes` finds the items in two files that are the same.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchKey {
+    /// Items with the same text are the same.
+    Text,
+
+    /// Items with the same value for this tag, e.g. 'uuid', are the same, even if their text
+    /// differs. Items where it is missing are matched by text.
+    Tag(String),
+}
+
+/// What makes two tasks the same for `TaskpaperFile::dedupe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupeKey {
+    // Tasks with the same text are the same.
+    Text,
+
+    // Tasks with the same text and the same tags, in any order, are the same.
+    TextAndTags,
+}
+
+/// Where `TaskpaperFile::merge_from` puts the items of the other file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    // The top level items are appended to the top level items.
+    Append,
+
+    // The children of a top level project are appended to the top level project with the same
+    // text. Everything else is appended to the top level items.
+    ByProjectText,
+}
+
+/// An item together with its children, to build a subtree before inserting it with
+/// `TaskpaperFile::insert_tree`.
+#[derive(Debug, Clone)]
+pub struct ItemTree {
+    pub item: Item,
+    pub children: Vec<ItemTree>,
+}
+
+impl ItemTree {
+    pub fn new(item: Item) -> Self {
+        ItemTree {
+            item,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_children(item: Item, children: Vec<ItemTree>) -> Self {
+        ItemTree { item, children }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum Position<'a> {
     AsFirst,
     AsLast,
     AsFirstChildOf(&'a NodeId),
     AsLastChildOf(&'a NodeId),
+    // Inserts at the given index among the children, or as last child if there are fewer.
+    AsNthChildOf(&'a NodeId, usize),
+    Before(&'a NodeId),
     After(&'a NodeId),
 }
 
@@ -440,6 +1392,8 @@ impl TaskpaperFile {
             arena: Vec::new(),
             nodes: Vec::new(),
             path: None,
+            front_matter: None,
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -447,6 +1401,11 @@ impl TaskpaperFile {
         self.path.as_ref().map(|p| p as &Path)
     }
 
+    /// The values of the front matter of this file, non-string values formatted as TOML.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
     pub fn parse_file(path: impl AsRef<Path>) -> Result<Self> {
         Self::parse_file_with_content(&::std::fs::read_to_string(&path)?, path)
     }
@@ -458,60 +1417,296 @@ impl TaskpaperFile {
     }
 
     pub fn parse(input: &str) -> Result<Self> {
-        // TODO(sirver): Swift does not filter empty line and that feels more correct.
-        let mut it = input
-            .trim()
-            .lines()
-            .enumerate()
-            .filter(|(_line_index, line)| !line.trim().is_empty())
-            .peekable();
+        Self::parse_with_options(input, ParseOptions::default())
+    }
+
+    pub fn parse_with_options(input: &str, options: ParseOptions) -> Result<Self> {
+        let (front_matter, input, line_offset) = split_front_matter(input);
+        let mut metadata = BTreeMap::new();
+        let front_matter = front_matter.map(|(front_matter, table)| {
+            for (key, value) in table {
+                let value = match value {
+                    toml::Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                metadata.insert(key, value);
+            }
+            front_matter.to_string()
+        });
 
+        let mut it = content_lines(input, line_offset, options).peekable();
         let mut nodes = Vec::new();
         let mut arena = Vec::new();
 
         while let Some(_) = it.peek() {
-            nodes.push(parse_item(&mut it, &mut arena));
+            nodes.push(parse_item(&mut it, &mut arena, options));
         }
         Ok(TaskpaperFile {
             arena,
             nodes,
             path: None,
+            front_matter,
+            metadata,
         })
     }
 
-    fn register_item(&mut self, item: Item) -> NodeId {
-        self.arena.push(Node {
-            parent: None,
-            children: Vec::new(),
-            item,
-        });
-        NodeId(self.arena.len() - 1)
+    /// Parses 'input' like `parse`, but fails if an item is indented more than one level deeper
+    /// than its parent, e.g. a task directly below a project that is indented by two tabs.
+    pub fn parse_strict(input: &str) -> Result<Self> {
+        let tpf = Self::parse(input)?;
+        let first_jump = tpf
+            .iter()
+            .filter_map(|node| {
+                let from = tpf[tpf[node.id()].parent()?].item().indent;
+                let to = node.item().indent;
+                if to > from + 1 {
+                    Some((node.item().line_index()?, from, to))
+                } else {
+                    None
+                }
+            })
+            .min();
+        match first_jump {
+            Some((line_index, from, to)) => Err(Error::IndentJump {
+                line_index,
+                from,
+                to,
+            }),
+            None => Ok(tpf),
+        }
+    }
+
+    /// Parses 'input' like `parse`, but also returns warnings for lines that were accepted although
+    /// they are likely mistakes, like jumps in indentation or tag values that are never closed.
+    pub fn parse_with_warnings(input: &str) -> Result<(Self, Vec<ParseWarning>)> {
+        let tpf = Self::parse(input)?;
+        let (_, content, line_offset) = split_front_matter(input);
+
+        let mut warnings = Vec::new();
+        let mut previous_indent = 0;
+        for (line_index, line) in content_lines(content, line_offset, ParseOptions::default()) {
+            let mut warn = |kind| warnings.push(ParseWarning { line_index, kind });
+            let indent = find_indent(line);
+            if indent > previous_indent + 1 {
+                warn(ParseWarningKind::IndentJump {
+                    from: previous_indent,
+                    to: indent,
+                });
+            }
+            previous_indent = indent;
+
+            if line
+                .trim_start_matches('\t')
+                .starts_with(char::is_whitespace)
+            {
+                warn(ParseWarningKind::MixedIndentation);
+            }
+            if tag::has_unterminated_value(line) {
+                warn(ParseWarningKind::UnterminatedTag);
+            }
+            let item = line_to_item(line, ParseOptions::default());
+            // A task with only tags, like '- @next', is parsed as a note with the text '-'.
+            let is_empty = match item.kind {
+                ItemKind::Task | ItemKind::Project => item.text.is_empty(),
+                ItemKind::Note | ItemKind::RawLine => item.text == "-",
+            };
+            if is_empty {
+                warn(ParseWarningKind::EmptyText);
+            }
+        }
+        Ok((tpf, warnings))
+    }
+
+    /// Adds 'item' to the arena. If the text of the item spans multiple lines, it is split into
+    /// the item itself (keeping kind and tags) and one additional item of the same kind per extra
+    /// line. All returned nodes are unlinked, they are meant to become siblings in order.
+    fn register_item(&mut self, mut item: Item) -> Vec<NodeId> {
+        let mut lines = item
+            .text
+            .lines()
+            .map(sanitize_item_text)
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>()
+            .into_iter();
+        let mut items = Vec::new();
+        if let Some(first) = lines.next() {
+            let kind = item.kind.clone();
+            let indent = item.indent;
+            item.text = first;
+            items.push(item);
+            for line in lines {
+                let mut sibling = Item::new(kind.clone(), line);
+                sibling.indent = indent;
+                items.push(sibling);
+            }
+        } else {
+            items.push(item);
+        }
+
+        let mut node_ids = Vec::with_capacity(items.len());
+        for item in items {
+            self.arena.push(Node {
+                parent: None,
+                children: Vec::new(),
+                item,
+                leading_whitespace: None,
+            });
+            node_ids.push(NodeId(self.arena.len() - 1));
+        }
+        node_ids
+    }
+
+    /// Sorts the top level nodes by the key 'f' returns for them. Notes stay with the node they
+    /// follow, see `SortUnit`.
+    pub fn sort_nodes_by_key<K, F>(&mut self, f: F)
+    where
+        F: FnMut(&Node) -> K,
+        K: Ord,
+    {
+        let nodes = mem::take(&mut self.nodes);
+        self.nodes = sort_units_by_key(nodes, &self.arena, f);
+    }
+
+    /// Sorts the children of 'parent' by the key 'f' returns for them. The sort is stable and does
+    /// not touch the order of the grandchildren. Notes stay with the node they follow, see
+    /// `SortUnit`.
+    pub fn sort_children_by_key<K, F>(&mut self, parent: &NodeId, f: F)
+    where
+        F: FnMut(&Node) -> K,
+        K: Ord,
+    {
+        let children = mem::take(&mut self.arena[parent.0].children);
+        self.arena[parent.0].children = sort_units_by_key(children, &self.arena, f);
     }
 
-    pub fn sort_nodes_by_key<K, F>(&mut self, mut f: F)
+    /// Sorts the top level nodes and the children of every node by the key 'f' returns for them,
+    /// e.g. to order the tasks in every project by '@priority'.
+    pub fn sort_all_by_key<K, F>(&mut self, mut f: F)
     where
         F: FnMut(&Node) -> K,
         K: Ord,
     {
-        let mut nodes = mem::replace(&mut self.nodes, Vec::new());
-        nodes.sort_by_key(|id| f(&self.arena[id.0]));
-        self.nodes = nodes;
+        self.sort_nodes_by_key(&mut f);
+        let node_ids = self
+            .iter()
+            .map(|node| node.id().clone())
+            .collect::<Vec<_>>();
+        for node_id in node_ids {
+            self.sort_children_by_key(&node_id, &mut f);
+        }
+    }
+
+    /// Sorts the top level nodes by the value of 'tag' parsed as '%Y-%m-%d' date. Nodes without
+    /// the tag or with a malformed date are sorted last, keeping their order.
+    pub fn sort_nodes_by_tag_date(&mut self, tag: &str) {
+        self.sort_nodes_by_key(|node| {
+            let date = node.item().tags().value_as_date(tag);
+            (date.is_none(), date)
+        });
     }
 
+    /// Inserts 'item' at 'position' and returns its id. Should the text of 'item' contain newlines,
+    /// every additional line is inserted as a sibling directly after it, so that the item text
+    /// invariant holds.
     pub fn insert(&mut self, item: Item, position: Position) -> NodeId {
-        let node_id = self.register_item(item);
-        self.insert_node(node_id.clone(), position);
+        let mut node_ids = self.register_item(item).into_iter();
+        let node_id = node_ids
+            .next()
+            .expect("Always at least one registered item.");
+        self.insert_node(node_id.clone(), position)
+            .expect("A new node cannot contain 'position'.");
+
+        let mut previous_id = node_id.clone();
+        for sibling_id in node_ids {
+            self.arena[sibling_id.0].item.indent = self.arena[node_id.0].item.indent;
+            self.insert_node(sibling_id.clone(), Position::After(&previous_id))
+                .expect("A new node cannot contain 'position'.");
+            previous_id = sibling_id;
+        }
+        node_id
+    }
+
+    /// Inserts 'tree' at 'position' and returns the id of its root. The items are indented
+    /// according to their depth in the tree, whatever their 'indent' was before.
+    pub fn insert_tree(&mut self, tree: ItemTree, position: Position) -> NodeId {
+        let ItemTree { mut item, children } = tree;
+        item.indent = 0;
+        let node_id = self.insert(item, position);
+        for child in children {
+            self.insert_tree(child, Position::AsLastChildOf(&node_id));
+        }
         node_id
     }
 
-    pub fn insert_node(&mut self, node_id: NodeId, position: Position) {
-        // Ensure that the indentation of the child is at least the parent + 1.
+    /// Moves the node with 'node_id', including all its children, to 'position'. Returns
+    /// `Error::MoveIntoSubtree` and changes nothing if 'position' is relative to the node itself
+    /// or one of its descendants.
+    pub fn move_node(&mut self, node_id: NodeId, position: Position) -> Result<()> {
+        if self.is_in_subtree(&node_id, position) {
+            return Err(Error::MoveIntoSubtree);
+        }
+        self.detach(&node_id);
+        self.insert_node(node_id, position)
+    }
+
+    /// Returns true if 'position' is relative to 'node_id' or one of its descendants.
+    fn is_in_subtree(&self, node_id: &NodeId, position: Position) -> bool {
+        let anchor = match position {
+            Position::AsFirst | Position::AsLast => return false,
+            Position::AsFirstChildOf(id)
+            | Position::AsLastChildOf(id)
+            | Position::AsNthChildOf(id, _)
+            | Position::Before(id)
+            | Position::After(id) => id,
+        };
+        anchor == node_id || self.ancestors(anchor).any(|a| a.id() == node_id)
+    }
+
+    /// Exchanges the positions of 'a' and 'b', which must have the same parent or both be top
+    /// level. Returns `Error::NotSiblings` otherwise.
+    pub fn swap_nodes(&mut self, a: &NodeId, b: &NodeId) -> Result<()> {
+        let parent = self.arena[a.0].parent.clone();
+        if parent != self.arena[b.0].parent {
+            return Err(Error::NotSiblings);
+        }
+        let siblings = match parent {
+            Some(parent_id) => &mut self.arena[parent_id.0].children,
+            None => &mut self.nodes,
+        };
+        let pos_a = siblings.iter().position(|id| id == a);
+        let pos_b = siblings.iter().position(|id| id == b);
+        match (pos_a, pos_b) {
+            (Some(pos_a), Some(pos_b)) => {
+                siblings.swap(pos_a, pos_b);
+                self.debug_check_invariants();
+                Ok(())
+            }
+            // One of the nodes has been unlinked.
+            _ => Err(Error::NotSiblings),
+        }
+    }
+
+    /// Links the unlinked node 'node_id' into the tree at 'position'. Returns
+    /// `Error::MoveIntoSubtree` and changes nothing if 'position' is relative to the node itself
+    /// or one of its descendants, since that would create a cycle.
+    pub fn insert_node(&mut self, node_id: NodeId, position: Position) -> Result<()> {
+        if self.is_in_subtree(&node_id, position) {
+            return Err(Error::MoveIntoSubtree);
+        }
+        // Ensure that the indentation of the child is at least the parent + 1. The descendants of
+        // the child might then need more indentation too.
         let ensure_indent_larger_then_parent = |arena: &mut [Node], parent_id: &NodeId| {
-            let indent = cmp::max(
-                arena[parent_id.0].item().indent + 1,
-                arena[node_id.0].item().indent,
-            );
-            arena[node_id.0].item_mut().indent = indent;
+            let mut open = vec![(parent_id.clone(), node_id.clone())];
+            while let Some((parent_id, child_id)) = open.pop() {
+                let min_indent = arena[parent_id.0].item().indent + 1;
+                if arena[child_id.0].item().indent >= min_indent {
+                    continue;
+                }
+                arena[child_id.0].item_mut().indent = min_indent;
+                for grandchild_id in &arena[child_id.0].children {
+                    open.push((child_id.clone(), grandchild_id.clone()));
+                }
+            }
         };
 
         match position {
@@ -534,56 +1729,244 @@ impl TaskpaperFile {
                 self.arena[node_id.0].parent = Some(parent_id.clone());
                 self.arena[parent_id.0].children.push(node_id)
             }
-            Position::After(sibling_id) => {
-                let parent_id = self.arena[sibling_id.0].parent.clone().expect(
-                    "Passing Position::After with a node that has no parent is unexpected.",
-                );
-                ensure_indent_larger_then_parent(&mut self.arena, &parent_id);
+            Position::AsNthChildOf(parent_id, index) => {
+                ensure_indent_larger_then_parent(&mut self.arena, parent_id);
                 self.arena[node_id.0].parent = Some(parent_id.clone());
-                let parent_node = &mut self.arena[parent_id.0];
-                let position = parent_node
-                    .children
-                    .iter()
-                    .position(|id| *id == *sibling_id)
-                    .expect("Sibling not actually a child of parent.");
-                parent_node.children.insert(position + 1, node_id);
+                let children = &mut self.arena[parent_id.0].children;
+                children.insert(cmp::min(index, children.len()), node_id)
             }
+            Position::Before(sibling_id) | Position::After(sibling_id) => {
+                let offset = match position {
+                    Position::After(_) => 1,
+                    _ => 0,
+                };
+                match self.arena[sibling_id.0].parent.clone() {
+                    Some(parent_id) => {
+                        ensure_indent_larger_then_parent(&mut self.arena, &parent_id);
+                        self.arena[node_id.0].parent = Some(parent_id.clone());
+                        let parent_node = &mut self.arena[parent_id.0];
+                        let position = parent_node
+                            .children
+                            .iter()
+                            .position(|id| *id == *sibling_id)
+                            .expect("Sibling not actually a child of parent.");
+                        parent_node.children.insert(position + offset, node_id);
+                    }
+                    None => {
+                        self.arena[node_id.0].parent = None;
+                        let position = self
+                            .nodes
+                            .iter()
+                            .position(|id| *id == *sibling_id)
+                            .expect("Sibling not actually a top level node.");
+                        self.nodes.insert(position + offset, node_id);
+                    }
+                }
+            }
+        }
+        self.debug_check_invariants();
+        Ok(())
+    }
+
+    /// Assigns every item the index of the line it starts on in 'to_string(options)'. Blank notes
+    /// that are not written because they exceed the configured number of empty lines get no
+    /// 'line_index'. Items that were inserted after parsing have no 'line_index' and the ones of
+    /// the others are stale, and writing the file only borrows it, so call this after formatting a
+    /// modified file before reporting line numbers.
+    pub fn recompute_line_indices(&mut self, options: FormatOptions) {
+        // Leaving out the trailing newline does not move any item.
+        let options = FormatOptions {
+            trailing_newline: true,
+            ..options
         };
+        let mut writer = LineIndexWriter::default();
+        self.format(&mut writer, ExportStyle::Taskpaper, false, options)
+            .expect("Formatting should never fail.");
+        for node in &mut self.arena {
+            node.item.line_index = None;
+        }
+        for (node_id, line_index) in writer.line_indices {
+            self.arena[node_id.0].item.line_index = Some(line_index);
+        }
+    }
+
+    /// Sets the indentation of every item to the one implied by its position in the tree, i.e. 0
+    /// for top level items and one more than the parent for all others. Items that change forget
+    /// the whitespace they were parsed with, so that 'to_string_preserving' writes them with tabs.
+    /// Returns the number of items that changed.
+    pub fn normalize_indentation(&mut self) -> usize {
+        let mut num_changed = 0;
+        let mut open: Vec<(NodeId, u32)> = self.nodes.iter().map(|id| (id.clone(), 0)).collect();
+        while let Some((node_id, indent)) = open.pop() {
+            let node = &mut self.arena[node_id.0];
+            if node.item.indent != indent {
+                node.item.indent = indent;
+                node.leading_whitespace = None;
+                num_changed += 1;
+            }
+            open.extend(node.children.iter().map(|id| (id.clone(), indent + 1)));
+        }
+        num_changed
+    }
+
+    /// Verifies the structure of the tree: every reachable node appears exactly once, the parent
+    /// of every node points to the node that has it as child and children are indented more than
+    /// their parents. Nodes that have been unlinked, but not yet dropped by 'compact', are
+    /// ignored. Returns `Error::CorruptTree` describing the first violation found.
+    pub fn check_invariants(&self) -> Result<()> {
+        let mut seen = vec![false; self.arena.len()];
+        let mut open: Vec<(Option<&NodeId>, &NodeId)> =
+            self.nodes.iter().rev().map(|id| (None, id)).collect();
+        while let Some((parent_id, node_id)) = open.pop() {
+            let node = self
+                .arena
+                .get(node_id.0)
+                .ok_or_else(|| Error::CorruptTree(format!("node {} does not exist", node_id.0)))?;
+            if mem::replace(&mut seen[node_id.0], true) {
+                return Err(Error::CorruptTree(format!(
+                    "node {} appears more than once",
+                    node_id.0
+                )));
+            }
+            if node.parent.as_ref() != parent_id {
+                return Err(Error::CorruptTree(format!(
+                    "node {} has parent {:?}, but is a child of {:?}",
+                    node_id.0,
+                    node.parent.as_ref().map(|id| id.0),
+                    parent_id.map(|id| id.0)
+                )));
+            }
+            if let Some(parent_id) = parent_id {
+                let parent_indent = self.arena[parent_id.0].item.indent;
+                if node.item.indent <= parent_indent {
+                    return Err(Error::CorruptTree(format!(
+                        "node {} has indent {}, but its parent has indent {}",
+                        node_id.0, node.item.indent, parent_indent
+                    )));
+                }
+            }
+            open.extend(node.children.iter().rev().map(|id| (Some(node_id), id)));
+        }
+        Ok(())
+    }
+
+    /// Panics if 'check_invariants' fails. The check walks the whole tree, so it only runs in the
+    /// tests of this crate and never in the mutations of users of the library.
+    fn debug_check_invariants(&self) {
+        if cfg!(test) {
+            if let Err(err) = self.check_invariants() {
+                panic!("{}", err);
+            }
+        }
     }
 
+    /// Formats the file using 'options'. The `Display` implementation formats using
+    /// `FormatOptions::default()`, use this for styled output.
     pub fn to_string(&self, options: FormatOptions) -> String {
+        self.export(ExportStyle::Taskpaper, options)
+    }
+
+    /// Formats the file in 'style' using 'options'.
+    pub fn export(&self, style: ExportStyle, options: FormatOptions) -> String {
+        let mut buf = String::new();
+        self.format(&mut buf, style, false, options)
+            .expect("Formatting should never fail.");
+        buf
+    }
+
+    /// Like 'to_string', but items are indented with the exact whitespace they had in the parsed
+    /// file instead of tabs. Items that were not parsed are indented with tabs.
+    pub fn to_string_preserving(&self, options: FormatOptions) -> String {
         let mut buf = String::new();
-        print_nodes(self.nodes.clone(), &self.arena, &mut buf, 0, options)
+        self.format(&mut buf, ExportStyle::Taskpaper, true, options)
             .expect("Formatting should never fail.");
         buf
     }
 
+    fn format(
+        &self,
+        buf: &mut impl ItemWriter,
+        style: ExportStyle,
+        preserve_indentation: bool,
+        options: FormatOptions,
+    ) -> fmt::Result {
+        if let (ExportStyle::Taskpaper, Some(front_matter)) = (style, &self.front_matter) {
+            write!(buf, "---\n{}---\n", front_matter)?;
+        }
+        let nodes = self.nodes.clone();
+        if options.trailing_newline {
+            return print_nodes(
+                nodes,
+                &self.arena,
+                buf,
+                0,
+                style,
+                preserve_indentation,
+                options,
+            );
+        }
+        let mut out = String::new();
+        print_nodes(
+            nodes,
+            &self.arena,
+            &mut out,
+            0,
+            style,
+            preserve_indentation,
+            options,
+        )?;
+        buf.write_str(out.strip_suffix('\n').unwrap_or(&out))
+    }
+
     pub fn node_to_string(&self, node_id: &NodeId) -> String {
         let mut buf = String::new();
-        let item = self.arena[node_id.0].item();
-        match &item.kind {
-            ItemKind::Project => append_project_to_string(item, &mut buf, 0)
-                .expect("Writing to string should always work."),
-            ItemKind::Task => append_task_to_string(item, &mut buf, 0)
-                .expect("Writing to string should always work."),
-            ItemKind::Note => append_note_to_string(item, &mut buf, 0)
-                .expect("Writing to string should always work."),
-        };
+        append_item_to_string(
+            self.arena[node_id.0].item(),
+            &mut buf,
+            "",
+            FormatOptions::default(),
+        )
+        .expect("Writing to string should always work.");
         buf
     }
 
-    pub fn write(&self, path: impl AsRef<Path>, options: FormatOptions) -> Result<()> {
+    /// Writes this file to 'path', unless the file there already has the same content. Returns
+    /// true if the file was written.
+    pub fn write(&self, path: impl AsRef<Path>, options: FormatOptions) -> Result<bool> {
+        let path = path.as_ref();
         let new = self.to_string(options);
+        if !has_changed(path, &new) {
+            return Ok(false);
+        }
+        std::fs::write(path, &new)?;
+        Ok(true)
+    }
 
-        let has_changed = match std::fs::read_to_string(&path) {
-            Err(_) => true,
-            Ok(old) => sha1_smol::Sha1::from(&old) != sha1_smol::Sha1::from(&new),
-        };
+    /// Like 'write', but always writes the file, even if its content would not change.
+    pub fn write_always(&self, path: impl AsRef<Path>, options: FormatOptions) -> Result<()> {
+        std::fs::write(path, self.to_string(options))?;
+        Ok(())
+    }
 
-        if has_changed {
-            std::fs::write(&path, new)?;
+    /// Like 'write', but writes into a temporary file next to 'path' first and renames it over
+    /// 'path', so that a crash never leaves a truncated file behind.
+    pub fn write_atomically(&self, path: impl AsRef<Path>, options: FormatOptions) -> Result<bool> {
+        let path = path.as_ref();
+        let new = self.to_string(options);
+        if !has_changed(path, &new) {
+            return Ok(false);
         }
-        Ok(())
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(path.file_name().unwrap_or_default());
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        if let Err(e) =
+            std::fs::write(&tmp_path, &new).and_then(|_| std::fs::rename(&tmp_path, path))
+        {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        Ok(true)
     }
 
     /// Return all objects that match 'query' in order of appearance in the file.
@@ -592,10 +1975,22 @@ impl TaskpaperFile {
         Ok(self.search_expr(&expr))
     }
 
+    /// Returns the only node matching 'query', e.g. to insert relative to it with
+    /// `Position::After(&node_id)`. Returns `Error::NoMatch` or `Error::AmbiguousMatch` if not
+    /// exactly one node matches.
+    pub fn find_unique(&self, query: &str) -> Result<NodeId> {
+        let mut node_ids = self.search(query)?;
+        match node_ids.len() {
+            0 => Err(Error::NoMatch(query.to_string())),
+            1 => Ok(node_ids.remove(0)),
+            n => Err(Error::AmbiguousMatch(query.to_string(), n)),
+        }
+    }
+
     pub fn search_expr(&self, expr: &search::Expr) -> Vec<NodeId> {
         let mut out = Vec::new();
         for node in self {
-            if expr.evaluate(&node.item().tags).is_truish() {
+            if node_matches(&self.arena, node.id(), expr) {
                 out.push(node.id().clone());
             }
         }
@@ -603,22 +1998,38 @@ impl TaskpaperFile {
     }
 
     /// Removes all items from 'self' that match 'query' and return them in the returned value.
-    /// If a parent item matches, the children are not tested further.
-    pub fn filter(&mut self, query: &str) -> Result<Vec<NodeId>> {
+    /// With `FilterMode::PruneSubtree`, the children of a matching item are not tested further and
+    /// are removed with it. With `FilterMode::TestAll`, the returned items lose their children:
+    /// matching children are returned on their own, the others stay in the file.
+    pub fn filter(&mut self, query: &str, mode: FilterMode) -> Result<Vec<NodeId>> {
         fn recurse(
             arena: &mut [Node],
             node_ids: Vec<NodeId>,
+            parent: Option<&NodeId>,
             expr: &search::Expr,
+            mode: FilterMode,
             filtered: &mut Vec<NodeId>,
         ) -> Vec<NodeId> {
             let mut retained = Vec::new();
             for node_id in node_ids {
-                if expr.evaluate(&arena[node_id.0].item.tags).is_truish() {
+                let matches = node_matches(arena, &node_id, expr);
+                if matches {
+                    // Filtered nodes are no longer part of the file.
+                    arena[node_id.0].parent = None;
+                }
+                if matches && mode == FilterMode::PruneSubtree {
+                    filtered.push(node_id);
+                    continue;
+                }
+                let children = mem::take(&mut arena[node_id.0].children);
+                if matches {
                     filtered.push(node_id);
+                    retained.extend(recurse(arena, children, parent, expr, mode, filtered));
                 } else {
-                    retained.push(node_id.clone());
-                    let children = mem::replace(&mut arena[node_id.0].children, Vec::new());
-                    arena[node_id.0].children = recurse(arena, children, expr, filtered);
+                    arena[node_id.0].parent = parent.cloned();
+                    arena[node_id.0].children =
+                        recurse(arena, children, Some(&node_id), expr, mode, filtered);
+                    retained.push(node_id);
                 }
             }
             retained
@@ -626,30 +2037,120 @@ impl TaskpaperFile {
 
         let expr = search::Expr::parse(query)?;
         let mut filtered = Vec::new();
-        let nodes = mem::replace(&mut self.nodes, Vec::new());
-        self.nodes = recurse(&mut self.arena, nodes, &expr, &mut filtered);
+        let nodes = mem::take(&mut self.nodes);
+        self.nodes = recurse(&mut self.arena, nodes, None, &expr, mode, &mut filtered);
+        self.debug_check_invariants();
         Ok(filtered)
     }
 
+    /// Removes all items that do not match 'query', except for the ancestors of matches, so that a
+    /// matching task stays inside its projects, and the notes of all kept items. This is the
+    /// inverse of 'filter', but drops the removed items instead of returning them.
+    pub fn retain(&mut self, query: &str) -> Result<()> {
+        let mut keep = HashSet::new();
+        for node_id in self.search(query)? {
+            keep.extend(self.ancestors(&node_id).map(|a| a.id().clone()));
+            keep.insert(node_id);
+        }
+        let mut open = keep.iter().cloned().collect::<Vec<_>>();
+        while let Some(node_id) = open.pop() {
+            for child in &self.arena[node_id.0].children {
+                if self.arena[child.0].item.is_note() && keep.insert(child.clone()) {
+                    open.push(child.clone());
+                }
+            }
+        }
+        // Removing the topmost items that are not kept also removes all their descendants, none of
+        // which can be kept, since they would keep their ancestors as well.
+        let to_remove = self
+            .iter()
+            .map(|n| n.id().clone())
+            .filter(|id| {
+                let parent = &self.arena[id.0].parent;
+                !keep.contains(id) && parent.as_ref().is_none_or(|p| keep.contains(p))
+            })
+            .collect::<Vec<_>>();
+        for node_id in to_remove {
+            self.unlink_node(node_id, ChildrenStrategy::Remove);
+        }
+        Ok(())
+    }
+
     /// Copy the node with 'source_id' from 'source' into us, including its entry and all sub
     /// nodes. Does not link it into the file tree, this needs to be done later manually.
     pub fn copy_node(&mut self, source: &TaskpaperFile, source_id: &NodeId) -> NodeId {
-        fn recurse(arena: &mut Vec<Node>, source: &TaskpaperFile, source_id: &NodeId) -> NodeId {
+        self.copy_node_filtered(source, source_id, |_| true)
+    }
+
+    /// Like 'copy_node', but only copies the descendants for which 'predicate' returns true. If a
+    /// descendant is skipped, so are its children. The node itself is always copied, e.g. a task
+    /// with only its notes can be copied with `|item| item.is_note()`.
+    pub fn copy_node_filtered(
+        &mut self,
+        source: &TaskpaperFile,
+        source_id: &NodeId,
+        predicate: impl Fn(&Item) -> bool,
+    ) -> NodeId {
+        fn recurse(
+            arena: &mut Vec<Node>,
+            source: &TaskpaperFile,
+            source_id: &NodeId,
+            predicate: &dyn Fn(&Item) -> bool,
+        ) -> NodeId {
             let id = NodeId(arena.len());
             let source_node = &source.arena[source_id.0];
             arena.push(Node {
                 parent: None,
                 item: source_node.item().clone(),
                 children: Vec::new(),
+                leading_whitespace: source_node.leading_whitespace.clone(),
             });
             let mut children = Vec::with_capacity(source_node.children.len());
             for child_id in &source_node.children {
-                children.push(recurse(arena, source, child_id));
+                if !predicate(source[child_id].item()) {
+                    continue;
+                }
+                let child = recurse(arena, source, child_id, predicate);
+                arena[child.0].parent = Some(id.clone());
+                children.push(child);
             }
             arena[id.0].children = children;
             id
         }
-        recurse(&mut self.arena, source, source_id)
+        recurse(&mut self.arena, source, source_id, &predicate)
+    }
+
+    /// Copies all items of 'other' into this file, see `MergeStrategy` for where they end up.
+    pub fn merge_from(&mut self, other: TaskpaperFile, strategy: MergeStrategy) {
+        for other_id in &other.nodes {
+            let other_item = other[other_id].item();
+            let project_id = match strategy {
+                MergeStrategy::Append => None,
+                MergeStrategy::ByProjectText if other_item.is_project() => self
+                    .nodes
+                    .iter()
+                    .find(|node_id| {
+                        let item = self[node_id].item();
+                        item.is_project() && item.text() == other_item.text()
+                    })
+                    .cloned(),
+                MergeStrategy::ByProjectText => None,
+            };
+            match project_id {
+                Some(project_id) => {
+                    for child_id in other[other_id].children() {
+                        let new_id = self.copy_node(&other, child_id);
+                        self.insert_node(new_id, Position::AsLastChildOf(&project_id))
+                            .expect("A new node cannot contain 'position'.");
+                    }
+                }
+                None => {
+                    let new_id = self.copy_node(&other, other_id);
+                    self.insert_node(new_id, Position::AsLast)
+                        .expect("A new node cannot contain 'position'.");
+                }
+            }
+        }
     }
 
     pub fn iter(&self) -> TaskpaperIter {
@@ -670,61 +2171,556 @@ impl TaskpaperFile {
         TaskpaperIter { tpf: self, open }
     }
 
+    /// Like 'iter_node', but without the node itself, i.e. only its children and their
+    /// descendants.
+    pub fn iter_descendants(&self, node_id: &NodeId) -> TaskpaperIter<'_> {
+        let open = self.arena[node_id.0].children.iter().cloned().collect();
+        TaskpaperIter { tpf: self, open }
+    }
+
+    /// Like 'iter', but also yields the depth of every item in the tree, 0 for top level items.
+    /// Unlike `Item::indent`, this is always one more than the depth of the parent.
+    pub fn iter_with_depth(&self) -> impl Iterator<Item = (usize, IterItem<'_>)> {
+        self.iter_with_depth_from(self.nodes.clone())
+    }
+
+    /// Like 'iter_node', but also yields the depth of every item relative to 'node_id', which
+    /// has depth 0.
+    pub fn iter_node_with_depth(
+        &self,
+        node_id: &NodeId,
+    ) -> impl Iterator<Item = (usize, IterItem<'_>)> {
+        self.iter_with_depth_from(vec![node_id.clone()])
+    }
+
+    fn iter_with_depth_from(
+        &self,
+        roots: Vec<NodeId>,
+    ) -> impl Iterator<Item = (usize, IterItem<'_>)> {
+        let mut open = roots
+            .into_iter()
+            .rev()
+            .map(|id| (0, id))
+            .collect::<Vec<_>>();
+        std::iter::from_fn(move || {
+            let (depth, node_id) = open.pop()?;
+            let node = &self.arena[node_id.0];
+            open.extend(node.children.iter().rev().map(|id| (depth + 1, id.clone())));
+            Some((depth, IterItem { node, node_id }))
+        })
+    }
+
     pub fn iter_node_mut(&mut self, node_id: &NodeId) -> TaskpaperIterMut {
         let mut open = VecDeque::new();
         open.push_back(node_id.clone());
         TaskpaperIterMut { tpf: self, open }
     }
 
-    /// Removes the node with the given 'node_id' from the File, i.e. unlinks it from its parent.
-    pub fn unlink_node(&mut self, node_id: NodeId) {
-        if self.arena[node_id.0].parent().is_some() {
-            let parent_id = self.arena[node_id.0].parent().unwrap().0;
-            let parent_node = &mut self.arena[parent_id];
-            let pos = parent_node
-                .children
-                .iter()
-                .position(|x| x.0 == node_id.0)
-                .expect("The parent of a node does not have this node as child.");
-            parent_node.children.remove(pos);
-        } else {
-            let pos = self
-                .nodes
-                .iter()
-                .position(|x| x.0 == node_id.0)
-                .expect("The parent of a node does not have this node as child.");
-            self.nodes.remove(pos);
+    /// Removes tasks that duplicate an earlier task according to 'by'. The children of a removed
+    /// task are moved to the end of the children of the task it duplicates, where they are
+    /// deduplicated in turn. Unless 'global' is set, only siblings are compared, so the same task
+    /// can still be in different projects. Returns the number of removed tasks.
+    pub fn dedupe(&mut self, by: DedupeKey, global: bool) -> usize {
+        let node_ids = self
+            .iter()
+            .map(|node| node.id().clone())
+            .collect::<Vec<_>>();
+        let mut seen = HashMap::new();
+        let mut num_removed = 0;
+        // Every node is visited after its parent, so children moved to an earlier task are still
+        // compared with their new siblings.
+        for node_id in node_ids {
+            let node = &self.arena[node_id.0];
+            if !node.item().is_task() {
+                continue;
+            }
+            let tags = match by {
+                DedupeKey::Text => Vec::new(),
+                DedupeKey::TextAndTags => node.item().tags().iter_sorted().collect(),
+            };
+            let scope = if global { None } else { node.parent.clone() };
+            let key = (scope, node.item().text().to_string(), tags);
+            let kept_id = match seen.get(&key) {
+                None => {
+                    seen.insert(key, node_id);
+                    continue;
+                }
+                Some(kept_id) => kept_id.clone(),
+            };
+            for child_id in mem::take(&mut self.arena[node_id.0].children) {
+                self.arena[child_id.0].parent = None;
+                self.insert_node(child_id, Position::AsLastChildOf(&kept_id))
+                    .expect("The kept task comes first, so it is not below the duplicate.");
+            }
+            self.unlink_node(node_id, ChildrenStrategy::Remove);
+            num_removed += 1;
         }
-        self.arena[node_id.0].parent = None;
+        num_removed
     }
-}
-
-impl<'a> Index<&'a NodeId> for TaskpaperFile {
-    type Output = Node;
 
-    fn index(&self, node_id: &'a NodeId) -> &Self::Output {
-        &self.arena[node_id.0]
+    /// Returns true if 'node_id' refers to a node that is currently part of the file, i.e. it is
+    /// reachable from the top level nodes.
+    pub fn contains_node(&self, node_id: &NodeId) -> bool {
+        if node_id.0 >= self.arena.len() {
+            return false;
+        }
+        let top_level = self.ancestors(node_id).last().map(|n| n.id().clone());
+        self.nodes.contains(top_level.as_ref().unwrap_or(node_id))
     }
-}
 
-impl<'a> IndexMut<&'a NodeId> for TaskpaperFile {
-    fn index_mut(&mut self, node_id: &'a NodeId) -> &mut Self::Output {
-        &mut self.arena[node_id.0]
+    /// Returns the number of ancestors of 'node_id', i.e. 0 for top-level nodes.
+    pub fn depth(&self, node_id: &NodeId) -> usize {
+        self.ancestors(node_id).count()
     }
-}
-
-// TODO(sirver): IterItem and IterItemMut seem unnecessary, they are essentially Nodes, but they
-// protect the nodes from being changed during iteration. Maybe that is worth having another layer
-// of abstraction over this.
-#[derive(Debug)]
-pub struct IterItem<'a> {
-    node: &'a Node,
-    node_id: NodeId,
-}
 
-impl<'a> IterItem<'a> {
-    pub fn item(&'a self) -> &'a Item {
-        &self.node.item
+    /// Adds 'tag' to every item matching 'query', replacing the value of an existing tag of the
+    /// same name. Returns the number of items that changed.
+    pub fn add_tag_where(&mut self, query: &str, tag: Tag) -> Result<usize> {
+        let mut num_changed = 0;
+        for node_id in self.search(query)? {
+            let tags = self.arena[node_id.0].item.tags_mut();
+            if tags.get(&tag.name).as_ref() != Some(&tag) {
+                tags.insert(tag.clone());
+                num_changed += 1;
+            }
+        }
+        Ok(num_changed)
+    }
+
+    /// Removes the tag 'name' from every item matching 'query'. Returns the number of items that
+    /// changed.
+    pub fn remove_tag_where(&mut self, query: &str, name: &str) -> Result<usize> {
+        let mut num_changed = 0;
+        for node_id in self.search(query)? {
+            let tags = self.arena[node_id.0].item.tags_mut();
+            if tags.contains(name) {
+                tags.remove(name);
+                num_changed += 1;
+            }
+        }
+        Ok(num_changed)
+    }
+
+    /// Adds a tag 'tag_name' with a new random UUID as value to every task and project that does
+    /// not have a value for it yet. Existing values are kept. Returns the number of items that
+    /// changed.
+    pub fn ensure_ids(&mut self, tag_name: &str) -> usize {
+        let node_ids = self
+            .iter()
+            .map(|node| node.id().clone())
+            .collect::<Vec<_>>();
+        let mut num_changed = 0;
+        for node_id in node_ids {
+            let item = &mut self.arena[node_id.0].item;
+            let is_task_or_project = item.is_task() || item.is_project();
+            if !is_task_or_project || item.tags().get_value(tag_name).is_some() {
+                continue;
+            }
+            item.tags_mut()
+                .insert(Tag::new(tag_name.to_string(), Some(new_uuid())));
+            num_changed += 1;
+        }
+        num_changed
+    }
+
+    /// Gives every task and project whose value for 'tag_name' was already seen earlier in the
+    /// file a new random UUID, so that the values are unique afterwards. Returns the number of
+    /// items that changed.
+    pub fn dedupe_ids(&mut self, tag_name: &str) -> usize {
+        let node_ids = self
+            .iter()
+            .map(|node| node.id().clone())
+            .collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+        let mut num_changed = 0;
+        for node_id in node_ids {
+            let item = &mut self.arena[node_id.0].item;
+            if !item.is_task() && !item.is_project() {
+                continue;
+            }
+            let value = match item.tags().get_value(tag_name) {
+                Some(value) => value.to_string(),
+                None => continue,
+            };
+            if !seen.insert(value) {
+                item.tags_mut()
+                    .insert(Tag::new(tag_name.to_string(), Some(new_uuid())));
+                num_changed += 1;
+            }
+        }
+        num_changed
+    }
+
+    /// Replaces all occurrences of 'from' with 'to' in the text of all items and sanitizes the
+    /// result using `sanitize_item_text`. Raw lines are not touched. Returns the number of items
+    /// that changed.
+    pub fn replace_in_text(&mut self, from: &str, to: &str) -> usize {
+        let mut num_changed = 0;
+        for mut node in self.iter_mut() {
+            let item = node.item_mut();
+            if item.kind == ItemKind::RawLine || !item.text.contains(from) {
+                continue;
+            }
+            let text = sanitize_item_text(&item.text.replace(from, to));
+            if text != item.text {
+                item.text = text;
+                num_changed += 1;
+            }
+        }
+        num_changed
+    }
+
+    /// Calls 'f' with name and value of every tag that has a value and replaces the value with the
+    /// result, if any. Returns the number of tags that changed.
+    pub fn rewrite_tag_values<F: Fn(&str, &str) -> Option<String>>(&mut self, f: F) -> usize {
+        let mut num_changed = 0;
+        for mut node in self.iter_mut() {
+            num_changed += node.item_mut().tags_mut().rewrite_values(&f);
+        }
+        num_changed
+    }
+
+    /// Counts the items in this file. Due dates that are not formatted as '%Y-%m-%d' are never
+    /// overdue.
+    pub fn summary(&self, today: chrono::NaiveDate) -> FileSummary {
+        let mut summary = FileSummary::default();
+        for node in self {
+            let item = node.item();
+            match item.kind {
+                ItemKind::Task => summary.tasks += 1,
+                ItemKind::Project => summary.projects += 1,
+                ItemKind::Note => summary.notes += 1,
+                ItemKind::RawLine => (),
+            }
+            let tags = item.tags();
+            let is_done = tags.get("done").is_some();
+            if is_done {
+                summary.done += 1;
+            }
+            let due = tags.value_as_date("due");
+            if !is_done && matches!(due, Some(due) if due < today) {
+                summary.overdue += 1;
+            }
+            let names: HashSet<String> = tags.iter().map(|t| t.name).collect();
+            for name in names {
+                *summary.tags.entry(name).or_insert(0) += 1;
+            }
+        }
+        summary
+    }
+
+    /// Like 'summary', with overdue counted against today's date according to 'clock', e.g.
+    /// `Config::clock`.
+    pub fn stats(&self, clock: &dyn Clock) -> FileSummary {
+        self.summary(clock.today())
+    }
+
+    /// Moves every item tagged '@done' into 'logbook', together with its children. Items without
+    /// a '@done' value get today's date according to 'clock', e.g. `Config::clock`. The items are
+    /// put into top level projects named by their done date formatted with 'date_format', their
+    /// text is prefixed with the texts of their former parents, e.g. 'Project • Task'. The
+    /// projects of the logbook are sorted newest first, projects that are not a date go last.
+    /// Returns `Error::InvalidDate` and changes nothing if a '@done' value is not a '%Y-%m-%d'
+    /// date.
+    pub fn archive_done(
+        &mut self,
+        logbook: &mut TaskpaperFile,
+        date_format: &str,
+        clock: &dyn Clock,
+    ) -> Result<()> {
+        let today = clock.today();
+        // Deepest items first. This allows us to process (and unlink) them in order without
+        // changing the structure of the file.
+        let mut done = Vec::new();
+        for node_id in self.search("@done")? {
+            let tags = self.arena[node_id.0].item.tags();
+            if let Some(value) = tags.get_value("done") {
+                if tags.value_as_date("done").is_none() {
+                    return Err(Error::InvalidDate(value.to_string()));
+                }
+            }
+            done.push((cmp::Reverse(self.depth(&node_id)), node_id));
+        }
+        done.sort_by_key(|(depth, _)| *depth);
+
+        for (_, source_node_id) in done {
+            let node_id = logbook.copy_node(self, &source_node_id);
+            let mut texts = vec![self[&source_node_id].item().text().to_string()];
+            for ancestor in self.ancestors(&source_node_id) {
+                texts.push(ancestor.item().text().to_string());
+            }
+            texts.reverse();
+            self.unlink_node(source_node_id, ChildrenStrategy::Remove);
+
+            let item = logbook[&node_id].item_mut();
+            item.text = texts.join(" • ");
+            let date = match item.tags().value_as_date("done") {
+                Some(date) => date,
+                None => {
+                    let value = today.format("%Y-%m-%d").to_string();
+                    item.tags_mut()
+                        .insert(Tag::new("done".to_string(), Some(value)));
+                    today
+                }
+            };
+            let heading = date.format(date_format).to_string();
+            let project_id =
+                match logbook.nodes.iter().find(|id| {
+                    logbook[id].item().is_project() && logbook[id].item().text == heading
+                }) {
+                    Some(project_id) => project_id.clone(),
+                    None => logbook.insert(Item::new(ItemKind::Project, heading), Position::AsLast),
+                };
+            logbook
+                .insert_node(node_id, Position::AsLastChildOf(&project_id))
+                .expect("A copied node cannot contain the logbook project.");
+        }
+        logbook.sort_nodes_by_key(|node| {
+            let date = chrono::NaiveDate::parse_from_str(node.item().text(), date_format).ok();
+            (date.is_none(), cmp::Reverse(date))
+        });
+        Ok(())
+    }
+
+    /// Pairs the tasks and projects of this file with the ones in 'other' according to
+    /// 'match_key'. Items with the same text are paired in order. Returns (own id, other id) in the
+    /// order of this file.
+    fn pair_items(&self, other: &TaskpaperFile, match_key: &MatchKey) -> Vec<(NodeId, NodeId)> {
+        let key_of = |item: &Item| -> Option<String> {
+            match match_key {
+                MatchKey::Text => None,
+                MatchKey::Tag(name) => item.tags().get_value(name).map(|v| v.to_string()),
+            }
+        };
+        let own_items = self
+            .iter()
+            .filter(|node| !node.item().is_note())
+            .collect::<Vec<_>>();
+        let other_items = other
+            .iter()
+            .filter(|node| !node.item().is_note())
+            .collect::<Vec<_>>();
+
+        // First pair everything with the same key, then fall back to the text for the rest.
+        let mut pairs: HashMap<NodeId, NodeId> = HashMap::new();
+        let mut by_key: HashMap<String, NodeId> = HashMap::new();
+        for node in &other_items {
+            if let Some(key) = key_of(node.item()) {
+                by_key.entry(key).or_insert_with(|| node.id().clone());
+            }
+        }
+        for node in &own_items {
+            if let Some(other_id) = key_of(node.item()).and_then(|key| by_key.remove(&key)) {
+                pairs.insert(node.id().clone(), other_id);
+            }
+        }
+
+        let paired_in_other: HashSet<NodeId> = pairs.values().cloned().collect();
+        let mut candidates: HashMap<String, VecDeque<&IterItem>> = HashMap::new();
+        for node in other_items
+            .iter()
+            .filter(|node| !paired_in_other.contains(node.id()))
+        {
+            candidates
+                .entry(node.item().normalized_text("•"))
+                .or_default()
+                .push_back(node);
+        }
+        for node in &own_items {
+            if pairs.contains_key(node.id()) {
+                continue;
+            }
+            let has_key = key_of(node.item()).is_some();
+            let other_node = candidates
+                .get_mut(&node.item().normalized_text("•"))
+                .and_then(|candidates| {
+                    // Items that both have a key, but a different one, are not the same.
+                    let index = candidates
+                        .iter()
+                        .position(|other_node| !has_key || key_of(other_node.item()).is_none())?;
+                    candidates.remove(index)
+                });
+            if let Some(other_node) = other_node {
+                pairs.insert(node.id().clone(), other_node.id().clone());
+            }
+        }
+
+        own_items
+            .iter()
+            .filter_map(|node| Some((node.id().clone(), pairs.remove(node.id())?)))
+            .collect()
+    }
+
+    /// The texts of the notes directly below 'node_id'.
+    fn note_texts(&self, node_id: &NodeId) -> Vec<&str> {
+        self.arena[node_id.0]
+            .children
+            .iter()
+            .map(|child_id| self[child_id].item())
+            .filter(|item| item.is_note())
+            .map(|item| item.text())
+            .collect()
+    }
+
+    /// Compares this file, the old version, to 'other', the new version. Tasks and projects are
+    /// matched by their text, notes are considered part of the item they belong to. Items that
+    /// exist in both versions are reported as modified if their kind, tags or notes differ.
+    /// Removed and modified items come first in the order of this file, followed by the added
+    /// items in the order of 'other'.
+    pub fn diff(&self, other: &TaskpaperFile) -> Vec<Change> {
+        let pairs: HashMap<NodeId, NodeId> = self
+            .pair_items(other, &MatchKey::Text)
+            .into_iter()
+            .collect();
+        let paired_in_other: HashSet<&NodeId> = pairs.values().collect();
+
+        let mut changes = Vec::new();
+        for node in self.iter().filter(|node| !node.item().is_note()) {
+            let old = node.id();
+            let new = match pairs.get(old) {
+                Some(new) => new,
+                None => {
+                    changes.push(Change::Removed(old.clone()));
+                    continue;
+                }
+            };
+            let (old_item, new_item) = (node.item(), other[new].item());
+            if old_item.kind != new_item.kind
+                || old_item.tags() != new_item.tags()
+                || self.note_texts(old) != other.note_texts(new)
+            {
+                changes.push(Change::Modified {
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+            }
+        }
+        for node in other.iter().filter(|node| !node.item().is_note()) {
+            if !paired_in_other.contains(node.id()) {
+                changes.push(Change::Added(node.id().clone()));
+            }
+        }
+        changes
+    }
+
+    /// Iterates over the parents of 'node_id', starting with the nearest and ending with the
+    /// top-level node. The node itself is not included.
+    pub fn ancestors(&self, node_id: &NodeId) -> AncestorsIter<'_> {
+        AncestorsIter {
+            tpf: self,
+            next: self.arena[node_id.0].parent.clone(),
+        }
+    }
+
+    /// Removes the node with the given 'node_id' from the File, i.e. unlinks it from its parent.
+    /// 'strategy' decides what happens to its children.
+    pub fn unlink_node(&mut self, node_id: NodeId, strategy: ChildrenStrategy) {
+        let parent = self.arena[node_id.0].parent.clone();
+        let pos = self.detach(&node_id);
+        let children = match strategy {
+            ChildrenStrategy::Remove => return,
+            ChildrenStrategy::Reparent | ChildrenStrategy::Promote => {
+                mem::take(&mut self.arena[node_id.0].children)
+            }
+        };
+        let (parent, siblings, pos) = match (strategy, parent) {
+            (ChildrenStrategy::Reparent, Some(parent_id)) => (
+                Some(parent_id.clone()),
+                &mut self.arena[parent_id.0].children,
+                pos,
+            ),
+            (ChildrenStrategy::Reparent, None) => (None, &mut self.nodes, pos),
+            _ => {
+                let len = self.nodes.len();
+                (None, &mut self.nodes, len)
+            }
+        };
+        siblings.splice(pos..pos, children.iter().cloned());
+        for child_id in children {
+            self.arena[child_id.0].parent = parent.clone();
+        }
+        self.debug_check_invariants();
+    }
+
+    /// Drops all nodes that are no longer reachable, e.g. after 'unlink_node' or 'filter', from
+    /// the internal storage. This invalidates all outstanding `NodeId`s of this file.
+    pub fn compact(&mut self) {
+        let mut old_arena: Vec<Option<Node>> =
+            mem::take(&mut self.arena).into_iter().map(Some).collect();
+        let mut new_ids = vec![None; old_arena.len()];
+
+        // Assign new ids in file order, so that parents always precede their children.
+        let mut order = Vec::new();
+        let mut open: VecDeque<NodeId> = self.nodes.iter().cloned().collect();
+        while let Some(node_id) = open.pop_front() {
+            new_ids[node_id.0] = Some(NodeId(order.len()));
+            let node = old_arena[node_id.0].as_ref().unwrap();
+            for child_id in node.children.iter().rev() {
+                open.push_front(child_id.clone());
+            }
+            order.push(node_id);
+        }
+
+        let remap = |id: &NodeId| new_ids[id.0].clone().expect("Reachable node without id.");
+        for node_id in order {
+            let mut node = old_arena[node_id.0].take().unwrap();
+            node.parent = node.parent.as_ref().map(remap);
+            node.children = node.children.iter().map(remap).collect();
+            self.arena.push(node);
+        }
+        self.nodes = self.nodes.iter().map(remap).collect();
+        self.debug_check_invariants();
+    }
+
+    /// Unlinks 'node_id' together with its children from its parent and returns the position it
+    /// had among its siblings.
+    fn detach(&mut self, node_id: &NodeId) -> usize {
+        let siblings = match self.arena[node_id.0].parent.clone() {
+            Some(parent_id) => &mut self.arena[parent_id.0].children,
+            None => &mut self.nodes,
+        };
+        let pos = siblings
+            .iter()
+            .position(|x| x.0 == node_id.0)
+            .expect("The parent of a node does not have this node as child.");
+        siblings.remove(pos);
+        self.arena[node_id.0].parent = None;
+        pos
+    }
+}
+
+impl fmt::Display for TaskpaperFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.format(f, ExportStyle::Taskpaper, false, FormatOptions::default())
+    }
+}
+
+impl<'a> Index<&'a NodeId> for TaskpaperFile {
+    type Output = Node;
+
+    fn index(&self, node_id: &'a NodeId) -> &Self::Output {
+        &self.arena[node_id.0]
+    }
+}
+
+impl<'a> IndexMut<&'a NodeId> for TaskpaperFile {
+    fn index_mut(&mut self, node_id: &'a NodeId) -> &mut Self::Output {
+        &mut self.arena[node_id.0]
+    }
+}
+
+// TODO(sirver): IterItem and IterItemMut seem unnecessary, they are essentially Nodes, but they
+// protect the nodes from being changed during iteration. Maybe that is worth having another layer
+// of abstraction over this.
+#[derive(Debug)]
+pub struct IterItem<'a> {
+    node: &'a Node,
+    node_id: NodeId,
+}
+
+impl<'a> IterItem<'a> {
+    pub fn item(&'a self) -> &'a Item {
+        &self.node.item
     }
 
     pub fn id(&self) -> &NodeId {
@@ -753,6 +2749,22 @@ impl<'a> Iterator for TaskpaperIter<'a> {
     }
 }
 
+pub struct AncestorsIter<'a> {
+    tpf: &'a TaskpaperFile,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for AncestorsIter<'a> {
+    type Item = IterItem<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node_id = self.next.take()?;
+        let node = &self.tpf.arena[node_id.0];
+        self.next = node.parent.clone();
+        Some(IterItem { node, node_id })
+    }
+}
+
 impl<'a> IntoIterator for &'a TaskpaperFile {
     type IntoIter = TaskpaperIter<'a>;
     type Item = IterItem<'a>;
@@ -827,6 +2839,7 @@ impl<'a> IntoIterator for &'a mut TaskpaperFile {
 pub fn mirror_changes(
     source_path: impl AsRef<Path>,
     destination: &mut TaskpaperFile,
+    match_key: MatchKey,
 ) -> Result<()> {
     if let Some(destination_path) = &destination.path {
         let source_path = source_path.as_ref();
@@ -839,18 +2852,7 @@ pub fn mirror_changes(
     }
 
     let source = TaskpaperFile::parse_file(source_path)?;
-    let mut pairs = Vec::new();
-
-    for dest_node in destination.iter() {
-        if let Some(source_node) = source
-            .iter()
-            .find(|source_node| source_node.item().text() == dest_node.item().text())
-        {
-            pairs.push((source_node.id().clone(), dest_node.id().clone()));
-        }
-    }
-
-    for (source_id, destination_id) in pairs {
+    for (destination_id, source_id) in destination.pair_items(&source, &match_key) {
         // TODO(sirver): This needs reconsideration.
         // As for its children: we copy over all notes unchanged, but ignore every other
         // children. This is a tad iffy, because we remove and add children to the 'dest_node'
@@ -863,13 +2865,17 @@ pub fn mirror_changes(
             &destination[&destination_id].item().kind,
         ) {
             (ItemKind::Project, ItemKind::Project) | (ItemKind::Task, ItemKind::Task) => {
-                // Copy the data of the changed item over.
-                *destination[&destination_id].item_mut() = source_node.item().clone();
+                // Copy the data of the changed item over, but keep the indentation, which must
+                // match the position in the destination tree.
+                let item = destination[&destination_id].item_mut();
+                let indent = item.indent;
+                *item = source_node.item().clone();
+                item.indent = indent;
             }
             _ => continue,
         };
 
-        if source_node.children().is_empty() {
+        if source_node.is_leaf() {
             continue;
         }
 
@@ -881,7 +2887,7 @@ pub fn mirror_changes(
             .cloned()
             .collect::<Vec<_>>();
         for child_id in children_to_nuke {
-            destination.unlink_node(child_id);
+            destination.unlink_node(child_id, ChildrenStrategy::Remove);
         }
 
         // Copy all notes from other over.
@@ -890,13 +2896,107 @@ pub fn mirror_changes(
                 continue;
             }
             let dest_child_id = destination.copy_node(&source, source_child_id);
-            destination.insert_node(dest_child_id, Position::AsLastChildOf(&destination_id));
+            destination.insert_node(dest_child_id, Position::AsLastChildOf(&destination_id))?;
         }
     }
 
     Ok(())
 }
 
+/// Removes all done items that are inside a project from 'todo' and keeps count of them in a note
+/// 'Done: 3 this week (since 2024-01-01)' as first child of the nearest project. Only items done in
+/// the period that contains 'today' are collapsed, '@done' without a date counts as today. The
+/// count starts over once the period changes. Done items that still contain tasks or projects that
+/// are not done are kept, as are done items inside them. Done items inside a done item are removed
+/// with it and not counted. Returns the number of items that were collapsed.
+pub fn collapse_done(
+    todo: &mut TaskpaperFile,
+    period: DonePeriod,
+    today: chrono::NaiveDate,
+) -> usize {
+    let start = period.start(today);
+    let mut counts: Vec<(NodeId, usize)> = Vec::new();
+    let mut done = Vec::new();
+    for node_id in todo.search("@done").expect("Valid query.") {
+        if todo
+            .ancestors(&node_id)
+            .any(|a| a.item().tags().contains("done"))
+        {
+            continue;
+        }
+        let tags = todo[&node_id].item().tags();
+        let done_date = match tags.get_value("done") {
+            None => today,
+            Some(_) => match tags.value_as_date("done") {
+                Some(date) => date,
+                None => continue,
+            },
+        };
+        if done_date < start {
+            continue;
+        }
+        let has_open_descendants = todo.iter_descendants(&node_id).any(|n| {
+            let item = n.item();
+            (item.is_task() || item.is_project()) && !item.tags().contains("done")
+        });
+        if has_open_descendants {
+            continue;
+        }
+        let project_id = match todo.ancestors(&node_id).find(|a| a.item().is_project()) {
+            Some(project) => project.id().clone(),
+            None => continue,
+        };
+        match counts.iter_mut().find(|(id, _)| *id == project_id) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((project_id, 1)),
+        }
+        done.push(node_id);
+    }
+    for node_id in &done {
+        todo.unlink_node(node_id.clone(), ChildrenStrategy::Remove);
+    }
+
+    for (project_id, count) in counts {
+        let existing = todo[&project_id].children().iter().find_map(|id| {
+            let item = todo[id].item();
+            if !item.is_note() {
+                return None;
+            }
+            parse_done_summary(item.text(), period).map(|summary| (id.clone(), summary))
+        });
+        let (note_id, previous) = match existing {
+            Some((note_id, (previous, since))) if since == start => (note_id, previous),
+            Some((note_id, _)) => (note_id, 0),
+            None => {
+                let note_id = todo.insert(
+                    Item::new(ItemKind::Note, String::new()),
+                    Position::AsFirstChildOf(&project_id),
+                );
+                (note_id, 0)
+            }
+        };
+        todo[&note_id].item_mut().text = format!(
+            "Done: {} {} (since {})",
+            previous + count,
+            period.description(),
+            start.format("%Y-%m-%d")
+        );
+    }
+    done.len()
+}
+
+/// Returns the count and the start of the period of a note written by `collapse_done` for
+/// 'period'. Other notes return None, even if they start with 'Done: '.
+fn parse_done_summary(text: &str, period: DonePeriod) -> Option<(usize, chrono::NaiveDate)> {
+    let (count, rest) = text.strip_prefix("Done: ")?.split_once(' ')?;
+    let since = rest
+        .strip_prefix(period.description())?
+        .strip_prefix(" (since ")?
+        .strip_suffix(')')?;
+    let since = chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d").ok()?;
+    Some((count.parse().ok()?, since))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -970,6 +3070,25 @@ mod tests {
         assert_eq!(input, tpf.to_string(FormatOptions::default()));
     }
 
+    #[test]
+    fn test_roundtrip_fixtures() {
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            ..Default::default()
+        }
+        .with_tags_as_written();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tests/roundtrip");
+        let mut num_fixtures = 0;
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let input = std::fs::read_to_string(&path).unwrap();
+            let tpf = TaskpaperFile::parse(&input).unwrap();
+            assert_eq!(input, tpf.to_string(options), "Roundtrip of {:?}", path);
+            num_fixtures += 1;
+        }
+        assert!(num_fixtures > 0);
+    }
+
     #[test]
     fn test_reformatting_roundtrip() {
         let input = include_str!("tests/simple_project.taskpaper");
@@ -979,31 +3098,1738 @@ mod tests {
     }
 
     #[test]
-    fn test_format_task() {
+    fn test_ancestors() {
+        let tpf = TaskpaperFile::parse("A:\n\tB:\n\t\t- C\n- D\n").unwrap();
+        let find = |text: &str| {
+            tpf.iter()
+                .find(|n| n.item().text() == text)
+                .map(|n| n.id().clone())
+                .unwrap()
+        };
+        let c = &find("C");
+        let texts: Vec<String> = tpf
+            .ancestors(c)
+            .map(|n| n.item().text().to_string())
+            .collect();
+        assert_eq!(vec!["B", "A"], texts);
+
+        let d = &find("D");
+        assert_eq!(0, tpf.ancestors(d).count());
+    }
+
+    #[test]
+    fn test_depth() {
+        let tpf = TaskpaperFile::parse("A:\n\tB:\n\t\t- C\n\t- E\n- D\n").unwrap();
+        let depths: Vec<(String, usize)> = tpf
+            .iter()
+            .map(|n| (n.item().text().to_string(), tpf.depth(n.id())))
+            .collect();
+        let expected = vec![("A", 0), ("B", 1), ("C", 2), ("E", 1), ("D", 0)];
+        assert_eq!(
+            expected
+                .into_iter()
+                .map(|(t, d)| (t.to_string(), d))
+                .collect::<Vec<_>>(),
+            depths
+        );
+    }
+
+    #[test]
+    fn test_move_node() {
+        let mut tpf = TaskpaperFile::parse("A:\n\t- Task\n\t\tNote\nB:\n\t- Other\n").unwrap();
+        let find = |tpf: &TaskpaperFile, text: &str| {
+            tpf.iter()
+                .find(|n| n.item().text() == text)
+                .map(|n| n.id().clone())
+                .unwrap()
+        };
+        let task = find(&tpf, "Task");
+        let b = find(&tpf, "B");
+        tpf.move_node(task.clone(), Position::AsLastChildOf(&b))
+            .unwrap();
+        assert_eq!(
+            "A:\n\nB:\n\t- Other\n\t- Task\n\t\tNote\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        tpf.move_node(task.clone(), Position::AsFirst).unwrap();
+        assert_eq!(
+            "- Task\n\tNote\nA:\n\nB:\n\t- Other\n",
+            tpf.to_string(FormatOptions {
+                sort: Sort::Nothing,
+                ..Default::default()
+            })
+        );
+
+        let note = find(&tpf, "Note");
+        assert!(matches!(
+            tpf.move_node(task.clone(), Position::AsLastChildOf(&note)),
+            Err(Error::MoveIntoSubtree)
+        ));
+        assert!(matches!(
+            tpf.move_node(task.clone(), Position::After(&task)),
+            Err(Error::MoveIntoSubtree)
+        ));
+        assert_eq!(Some(&task), tpf[&note].parent());
+    }
+
+    #[test]
+    fn test_summary() {
         let tpf = TaskpaperFile::parse(
-            "- Arbeit • Foo • blah @blocked(arg prs) @coding @next @done(2018-06-21)",
+            "Project: @area(work)\n\
+             \t- Overdue @due(2020-01-01)\n\
+             \t- Due today @due(2020-01-02) @next\n\
+             \t- Done overdue @due(2019-12-01) @done(2019-12-01)\n\
+             \t\tA note\n\
+             - Top level @next\n",
         )
         .unwrap();
-        let golden = "- Arbeit • Foo • blah @coding @next @blocked(arg prs) @done(2018-06-21)\n";
-        assert_eq!(golden, tpf.to_string(FormatOptions::default()));
+        let summary = tpf.summary(chrono::NaiveDate::from_ymd_opt(2020, 1, 2).unwrap());
+        let tags = [("area", 1), ("done", 1), ("due", 3), ("next", 2)]
+            .iter()
+            .map(|(name, count)| (name.to_string(), *count))
+            .collect();
+        assert_eq!(
+            FileSummary {
+                tasks: 4,
+                projects: 1,
+                notes: 1,
+                done: 1,
+                overdue: 1,
+                tags,
+            },
+            summary
+        );
     }
 
     #[test]
-    fn test_mirror_changes_nothing_happens_when_destination_is_newer() {
-        let test = DatabaseTest::new();
-        let source = test.write_file(
-            "source.taskpaper",
-            include_str!("tests/mirror_changes/source.taskpaper"),
+    fn test_stats() {
+        let tpf = TaskpaperFile::parse(include_str!("tests/stats.taskpaper")).unwrap();
+        let clock = FixedClock(chrono::NaiveDate::from_ymd_opt(2020, 1, 15).unwrap());
+        let stats = tpf.stats(&clock);
+        assert_eq!(
+            (2, 5, 2, 2, 1),
+            (
+                stats.projects,
+                stats.tasks,
+                stats.notes,
+                stats.done,
+                stats.overdue
+            )
         );
-        let destination_path = test.write_file(
-            "destination.taskpaper",
-            include_str!("tests/mirror_changes/destination.taskpaper"),
+
+        // Nothing is overdue before the first due date.
+        let clock = FixedClock(chrono::NaiveDate::from_ymd_opt(2019, 12, 31).unwrap());
+        assert_eq!(0, tpf.stats(&clock).overdue);
+    }
+
+    #[test]
+    fn test_merge_from() {
+        let input = "Monday:\n\t- Dentist\nTuesday:\n\t- Gym\n";
+        let other = "Tuesday:\n\t- Groceries\n\t\tMilk and eggs\nWednesday:\n\t- Call mom\n";
+
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        tpf.merge_from(
+            TaskpaperFile::parse(other).unwrap(),
+            MergeStrategy::ByProjectText,
         );
-        let mut destination = TaskpaperFile::parse_file(&destination_path).unwrap();
-        mirror_changes(&source, &mut destination).expect("Should work.");
         assert_eq!(
-            &destination.to_string(FormatOptions::default()),
-            include_str!("tests/mirror_changes/destination.taskpaper"),
+            "Monday:\n\t- Dentist\n\nTuesday:\n\t- Gym\n\t- Groceries\n\t\tMilk and eggs\n\nWednesday:\n\t- Call mom\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        tpf.check_invariants().unwrap();
+
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        tpf.merge_from(TaskpaperFile::parse(other).unwrap(), MergeStrategy::Append);
+        assert_eq!(4, tpf.nodes.len());
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let input = "A:\n\t- Milk\n\t- Milk @next\n\t- Milk\n\t\t- Lactose free\nB:\n\t- Milk\n";
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        assert_eq!(1, tpf.dedupe(DedupeKey::TextAndTags, false));
+        assert_eq!(
+            "A:\n\t- Milk\n\t\t- Lactose free\n\t- Milk @next\n\nB:\n\t- Milk\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        assert_eq!(2, tpf.dedupe(DedupeKey::Text, false));
+        assert_eq!(
+            "A:\n\t- Milk\n\t\t- Lactose free\n\nB:\n\t- Milk\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        assert_eq!(3, tpf.dedupe(DedupeKey::Text, true));
+        assert_eq!(
+            "A:\n\t- Milk\n\t\t- Lactose free\n\nB:\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        tpf.check_invariants().unwrap();
+
+        // Children of a duplicate are deduplicated against the children of the kept task.
+        let input = "- Milk\n\t- Brand\n- Milk\n\t- Brand\n\t- Size\n";
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        assert_eq!(2, tpf.dedupe(DedupeKey::Text, false));
+        assert_eq!(
+            "- Milk\n\t- Brand\n\t- Size\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        tpf.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_swap_nodes() {
+        let mut tpf = TaskpaperFile::parse("A:\n\t- 1\n\t- 2\n\t- 3\nB:\n\t- 4\n").unwrap();
+        let find = |tpf: &TaskpaperFile, text: &str| {
+            tpf.iter()
+                .find(|n| n.item().text() == text)
+                .map(|n| n.id().clone())
+                .unwrap()
+        };
+        let (one, three) = (find(&tpf, "1"), find(&tpf, "3"));
+        tpf.swap_nodes(&one, &three).unwrap();
+        let (a, b) = (find(&tpf, "A"), find(&tpf, "B"));
+        tpf.swap_nodes(&a, &b).unwrap();
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            ..Default::default()
+        };
+        assert_eq!(
+            "B:\n\t- 4\n\nA:\n\t- 3\n\t- 2\n\t- 1\n",
+            tpf.to_string(options)
+        );
+
+        let four = find(&tpf, "4");
+        assert!(matches!(
+            tpf.swap_nodes(&one, &four),
+            Err(Error::NotSiblings)
+        ));
+        assert!(matches!(tpf.swap_nodes(&one, &a), Err(Error::NotSiblings)));
+    }
+
+    #[test]
+    fn test_unlink_node() {
+        let input = "A:\n\t- 1\n\tB:\n\t\t- 2\n\t\t- 3\n\t- 4\n- 5\n";
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            empty_line_after_project: EmptyLineAfterProject {
+                top_level: 0,
+                first_level: 0,
+                others: 0,
+            },
+            ..Default::default()
+        };
+        let unlink = |strategy| {
+            let mut tpf = TaskpaperFile::parse(input).unwrap();
+            let b = tpf
+                .iter()
+                .find(|n| n.item().text() == "B")
+                .map(|n| n.id().clone())
+                .unwrap();
+            tpf.unlink_node(b, strategy);
+            tpf.to_string(options)
+        };
+        assert_eq!("A:\n\t- 1\n\t- 4\n- 5\n", unlink(ChildrenStrategy::Remove));
+        assert_eq!(
+            "A:\n\t- 1\n\t- 2\n\t- 3\n\t- 4\n- 5\n",
+            unlink(ChildrenStrategy::Reparent)
+        );
+        assert_eq!(
+            "A:\n\t- 1\n\t- 4\n- 5\n- 2\n- 3\n",
+            unlink(ChildrenStrategy::Promote)
+        );
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let parse = || TaskpaperFile::parse("A:\n\t- 1\n\t\tNote\nB:\n\t- 2\n").unwrap();
+        parse().check_invariants().unwrap();
+
+        let mut duplicated = parse();
+        let child_id = duplicated.arena[duplicated.nodes[0].0].children[0].clone();
+        duplicated.nodes.push(child_id);
+        assert!(matches!(
+            duplicated.check_invariants(),
+            Err(Error::CorruptTree(_))
+        ));
+
+        let mut wrong_parent = parse();
+        let child_id = wrong_parent.arena[wrong_parent.nodes[0].0].children[0].clone();
+        wrong_parent.arena[child_id.0].parent = Some(wrong_parent.nodes[1].clone());
+        assert!(matches!(
+            wrong_parent.check_invariants(),
+            Err(Error::CorruptTree(_))
+        ));
+
+        let mut wrong_indent = parse();
+        let child_id = wrong_indent.arena[wrong_indent.nodes[0].0].children[0].clone();
+        wrong_indent.arena[child_id.0].item.indent = 0;
+        assert!(matches!(
+            wrong_indent.check_invariants(),
+            Err(Error::CorruptTree(_))
+        ));
+    }
+
+    #[test]
+    fn test_move_node_indents_descendants() {
+        let mut tpf = TaskpaperFile::parse("A:\n\t- 1\n\t\t- 2\nB:\n\t- 3\n").unwrap();
+        let a = tpf.nodes[0].clone();
+        let three = tpf.arena[tpf.nodes[1].0].children[0].clone();
+        tpf.move_node(a, Position::AsLastChildOf(&three)).unwrap();
+        tpf.check_invariants().unwrap();
+        assert_eq!(
+            "B:\n\t- 3\n\t\tA:\n\t\t\t- 1\n\t\t\t\t- 2\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert!(parse_duration("trnae").is_err());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("w").is_err());
+        assert!(parse_duration("2x").is_err());
+        assert!(parse_duration("0d").is_err());
+        assert!(parse_duration("-3d").is_err());
+        assert!(matches!(
+            parse_duration("99999999999999999y"),
+            Err(Error::InvalidDuration(_))
+        ));
+        assert_eq!(parse_duration("5d").unwrap(), chrono::Duration::days(5));
+        assert_eq!(parse_duration("2w").unwrap(), chrono::Duration::weeks(2));
+        assert_eq!(parse_duration("3m").unwrap(), chrono::Duration::days(90));
+        assert_eq!(
+            parse_duration("4y").unwrap(),
+            chrono::Duration::days(4 * 365)
+        );
+    }
+
+    #[test]
+    fn test_recurrence() {
+        let date = |y, m, d| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let next = |spec: &str, from| Recurrence::parse(spec).unwrap().next_after(from).unwrap();
+
+        assert_eq!(date(2024, 1, 8), next("1w", date(2024, 1, 1)));
+        assert_eq!(date(2024, 4, 1), next("3m", date(2024, 1, 2)));
+
+        // 2024-01-01 is a Monday.
+        assert_eq!(date(2024, 1, 8), next("weekly(mon)", date(2024, 1, 1)));
+        assert_eq!(date(2024, 1, 2), next("weekly(tue)", date(2024, 1, 1)));
+        assert_eq!(date(2024, 1, 7), next("weekly(Sunday)", date(2024, 1, 1)));
+        assert_eq!(date(2024, 1, 5), next("weekly( fri )", date(2024, 1, 3)));
+
+        assert_eq!(date(2024, 1, 20), next("monthly(20)", date(2024, 1, 15)));
+        assert_eq!(date(2024, 2, 1), next("monthly(1)", date(2024, 1, 1)));
+        assert_eq!(date(2024, 2, 1), next("monthly(1)", date(2024, 1, 31)));
+        assert_eq!(date(2024, 2, 29), next("monthly(31)", date(2024, 1, 31)));
+        assert_eq!(date(2024, 2, 29), next("monthly(30)", date(2024, 2, 10)));
+        assert_eq!(date(2023, 2, 28), next("monthly(29)", date(2023, 1, 29)));
+        assert_eq!(date(2024, 3, 31), next("monthly(31)", date(2024, 2, 29)));
+        assert_eq!(date(2024, 4, 30), next("monthly(31)", date(2024, 3, 31)));
+        assert_eq!(date(2025, 1, 31), next("monthly(31)", date(2024, 12, 31)));
+        assert_eq!(date(2025, 1, 15), next("monthly(15)", date(2024, 12, 15)));
+
+        // Recurrences never stay on the same day or go back.
+        let after = |days| Recurrence::After(chrono::Duration::days(days));
+        for days in [0, -3] {
+            assert!(matches!(
+                after(days).next_after(date(2024, 1, 1)),
+                Err(Error::InvalidDuration(_))
+            ));
+        }
+        assert!(matches!(
+            after(1).next_after(chrono::NaiveDate::MAX),
+            Err(Error::InvalidDate(_))
+        ));
+        assert!(matches!(
+            Recurrence::Monthly(1).next_after(chrono::NaiveDate::MAX),
+            Err(Error::InvalidDate(_))
+        ));
+
+        // Anchored values contain parentheses themselves.
+        let item = Item::parse_line("- Pay rent @repeat(monthly(1))");
+        assert_eq!(Some("monthly(1)"), item.tags().get_value("repeat"));
+
+        for invalid in [
+            "monthly(0)",
+            "monthly(32)",
+            "monthly()",
+            "weekly(someday)",
+            "weekly mon",
+            "daily",
+        ] {
+            assert!(
+                matches!(Recurrence::parse(invalid), Err(Error::InvalidDuration(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_today_in_configured_timezone() {
+        let mut test = DatabaseTest::new();
+        let mut config = |timezone: &str| {
+            test.write_config(&format!("timezone = '{}'", timezone));
+            test.read_database().config()
+        };
+        let now = chrono::DateTime::parse_from_rfc3339("2024-01-01T20:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+
+        let today = config("Pacific/Auckland").unwrap().today_at(now);
+        assert_eq!(chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap(), today);
+        assert_eq!(
+            chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            config("America/Los_Angeles").unwrap().today_at(now)
+        );
+        assert!(matches!(
+            config("Mars/Olympus_Mons"),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let mut todo = TaskpaperFile::parse("- Stamped @done\n").unwrap();
+        let mut logbook = TaskpaperFile::new();
+        todo.archive_done(&mut logbook, "%Y-%m-%d", &FixedClock(today))
+            .unwrap();
+        assert_eq!(
+            "2024-01-02:\n\t- Stamped @done(2024-01-02)\n",
+            logbook.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_archive_done() {
+        let mut todo =
+            TaskpaperFile::parse(include_str!("tests/archive_done/todo_in.taskpaper")).unwrap();
+        let mut logbook = TaskpaperFile::new();
+        let clock = FixedClock(chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap());
+        todo.archive_done(&mut logbook, "%A, %d. %B %Y", &clock)
+            .unwrap();
+
+        let logbook_format = FormatOptions {
+            sort: Sort::Nothing,
+            empty_line_after_project: EmptyLineAfterProject {
+                top_level: 2,
+                first_level: 0,
+                others: 0,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            include_str!("tests/archive_done/logbook_out.taskpaper"),
+            logbook.to_string(logbook_format)
+        );
+        assert_eq!(
+            include_str!("tests/archive_done/todo_out.taskpaper"),
+            todo.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_archive_done_invalid_date() {
+        let input = "A:\n\t- 1 @done(yesterday)\n\t- 2 @done\n";
+        let mut todo = TaskpaperFile::parse(input).unwrap();
+        let mut logbook = TaskpaperFile::new();
+        assert!(matches!(
+            todo.archive_done(&mut logbook, "%Y-%m-%d", &SystemClock::default()),
+            Err(Error::InvalidDate(_))
+        ));
+        assert_eq!(input, todo.to_string(FormatOptions::default()));
+        assert!(logbook.iter().next().is_none());
+    }
+
+    #[test]
+    fn test_find_unique() {
+        let mut tpf = TaskpaperFile::parse("A:\n\t- 1 @id(1)\n\t- 2 @x\n\t- 3 @x\n").unwrap();
+        let node_id = tpf.find_unique("@id = \"1\"").unwrap();
+        tpf.insert(
+            Item::new(ItemKind::Task, "New".to_string()),
+            Position::After(&node_id),
+        );
+        assert_eq!(
+            "A:\n\t- 1 @id(1)\n\t- New\n\t- 2 @x\n\t- 3 @x\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        assert!(matches!(
+            tpf.find_unique("@x"),
+            Err(Error::AmbiguousMatch(_, 2))
+        ));
+        assert!(matches!(
+            tpf.find_unique("@missing"),
+            Err(Error::NoMatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_copy_node_filtered() {
+        let source = TaskpaperFile::parse(
+            "- Task\n\tFirst note\n\t\tNested note\n\t- Subtask\n\t\tNote of subtask\n\tSecond note\n",
+        )
+        .unwrap();
+        let task = source.iter().next().unwrap().id().clone();
+        let mut destination = TaskpaperFile::new();
+        let copy = destination.copy_node_filtered(&source, &task, |item| item.is_note());
+        destination.insert_node(copy, Position::AsLast).unwrap();
+        assert_eq!(
+            "- Task\n\tFirst note\n\t\tNested note\n\tSecond note\n",
+            destination.to_string(FormatOptions::default())
+        );
+        destination.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_copy_node_sets_parents() {
+        let source = TaskpaperFile::parse("A:\n\t- 1\n\t\tNote\n").unwrap();
+        let mut tpf = TaskpaperFile::new();
+        let node_id = tpf.copy_node(&source, &source.nodes[0]);
+        tpf.insert_node(node_id, Position::AsLast).unwrap();
+        tpf.check_invariants().unwrap();
+        assert_eq!(
+            "A:\n\t- 1\n\t\tNote\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_collapse_done() {
+        let mut tpf = TaskpaperFile::parse(
+            "A:\n\t- 1 @done(2024-01-02)\n\t\t- 1.1 @done\n\t- 2\n\t- 3 @done\nB:\n\t- 4\n- 5 @done\n",
+        )
+        .unwrap();
+        let monday = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let wednesday = chrono::NaiveDate::from_ymd_opt(2024, 1, 3).unwrap();
+        assert_eq!(2, collapse_done(&mut tpf, DonePeriod::Week, wednesday));
+        assert_eq!(
+            "A:\n\tDone: 2 this week (since 2024-01-01)\n\t- 2\n\nB:\n\t- 4\n- 5 @done\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        // The count is added to the existing note.
+        let task_id = tpf
+            .iter()
+            .find(|n| n.item().text() == "2")
+            .unwrap()
+            .id()
+            .clone();
+        tpf[&task_id]
+            .item_mut()
+            .tags_mut()
+            .insert(Tag::new("done".to_string(), None));
+        assert_eq!(1, collapse_done(&mut tpf, DonePeriod::Week, monday));
+        assert_eq!(
+            "A:\n\tDone: 3 this week (since 2024-01-01)\n\nB:\n\t- 4\n- 5 @done\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        // In the next week the count starts over.
+        tpf.insert(
+            Item::new_with_tags(
+                ItemKind::Task,
+                "6".to_string(),
+                Tags::from_vec(vec![("done".to_string(), None)]),
+            ),
+            Position::AsLastChildOf(&tpf.nodes[0].clone()),
+        );
+        let next_week = chrono::NaiveDate::from_ymd_opt(2024, 1, 9).unwrap();
+        assert_eq!(1, collapse_done(&mut tpf, DonePeriod::Week, next_week));
+        assert_eq!(
+            "A:\n\tDone: 1 this week (since 2024-01-08)\n\nB:\n\t- 4\n- 5 @done\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_collapse_done_keeps_open_and_old_items() {
+        let mut tpf = TaskpaperFile::parse(
+            "A:\n\
+             \tDone: call the bank\n\
+             \t- Old @done(2023-12-20)\n\
+             \t- Parent @done(2024-01-02)\n\
+             \t\t- Open child\n\
+             \t- Recent @done(2024-01-02)\n",
+        )
+        .unwrap();
+        let wednesday = chrono::NaiveDate::from_ymd_opt(2024, 1, 3).unwrap();
+        assert_eq!(1, collapse_done(&mut tpf, DonePeriod::Week, wednesday));
+        assert_eq!(
+            "A:\n\
+             \tDone: 1 this week (since 2024-01-01)\n\
+             \tDone: call the bank\n\
+             \t- Old @done(2023-12-20)\n\
+             \t- Parent @done(2024-01-02)\n\
+             \t\t- Open child\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut tpf = TaskpaperFile::parse("A:\n\t- 1\n\t\tNote\nB:\n\t- 2 @x\n").unwrap();
+        for i in 0..100 {
+            let id = tpf.insert(
+                Item::new(ItemKind::Task, format!("Temporary {}", i)),
+                Position::AsLast,
+            );
+            tpf.unlink_node(id, ChildrenStrategy::Remove);
+        }
+        tpf.filter("@x", FilterMode::PruneSubtree).unwrap();
+        let before = tpf.to_string(FormatOptions::default());
+
+        tpf.compact();
+        assert_eq!(4, tpf.arena.len());
+        assert_eq!(tpf.iter().count(), tpf.arena.len());
+        assert_eq!(before, tpf.to_string(FormatOptions::default()));
+
+        let note = tpf.iter().find(|n| n.item().is_note()).unwrap();
+        let texts: Vec<String> = tpf
+            .ancestors(note.id())
+            .map(|n| n.item().text().to_string())
+            .collect();
+        assert_eq!(vec!["1", "A"], texts);
+    }
+
+    #[test]
+    fn test_contains_node() {
+        let mut tpf = TaskpaperFile::parse("A:\n\tB:\n\t\t- C\n- D\n").unwrap();
+        let find = |tpf: &TaskpaperFile, text: &str| {
+            tpf.iter()
+                .find(|n| n.item().text() == text)
+                .map(|n| n.id().clone())
+                .unwrap()
+        };
+        let (b, c, d) = (find(&tpf, "B"), find(&tpf, "C"), find(&tpf, "D"));
+        assert!(tpf.contains_node(&c));
+        assert!(tpf.contains_node(&d));
+        assert!(!tpf.contains_node(&NodeId(100)));
+
+        tpf.unlink_node(b.clone(), ChildrenStrategy::Remove);
+        assert!(!tpf.contains_node(&b));
+        assert!(!tpf.contains_node(&c));
+        assert!(tpf.contains_node(&d));
+
+        let mut tpf = TaskpaperFile::parse("A:\n\t- x @done\n\t- y\n").unwrap();
+        let (x, y) = (find(&tpf, "x"), find(&tpf, "y"));
+        assert_eq!(
+            vec![x.clone()],
+            tpf.filter("@done", FilterMode::PruneSubtree).unwrap()
+        );
+        assert!(!tpf.contains_node(&x));
+        assert!(tpf.contains_node(&y));
+        assert_eq!(0, tpf.depth(&x));
+        assert!(tpf.ancestors(&x).next().is_none());
+    }
+
+    #[test]
+    fn test_node_children() {
+        let tpf = TaskpaperFile::parse("A:\n\t- B\n\t- C\n").unwrap();
+        let a = &tpf[&tpf.nodes[0]];
+        assert!(a.has_children());
+        assert!(!a.is_leaf());
+        assert_eq!(2, a.child_count());
+
+        let b = &tpf[&a.children()[0]];
+        assert!(!b.has_children());
+        assert!(b.is_leaf());
+        assert_eq!(0, b.child_count());
+    }
+
+    #[test]
+    fn test_insert_before() {
+        let mut tpf = TaskpaperFile::parse("A:\n\t- 1\n\t- 2\nB:\n").unwrap();
+        let find = |tpf: &TaskpaperFile, text: &str| {
+            tpf.iter()
+                .find(|n| n.item().text() == text)
+                .map(|n| n.id().clone())
+                .unwrap()
+        };
+        let (a, two) = (find(&tpf, "A"), find(&tpf, "2"));
+        tpf.insert(
+            Item::new(ItemKind::Project, "Top".to_string()),
+            Position::Before(&a),
+        );
+        let nested = tpf.insert(
+            Item::new(ItemKind::Task, "Nested".to_string()),
+            Position::Before(&two),
+        );
+        assert_eq!(1, tpf[&nested].item().indent);
+        assert_eq!(
+            "Top:\n\nA:\n\t- 1\n\t- Nested\n\t- 2\n\nB:\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_insert_as_nth_child() {
+        let mut tpf = TaskpaperFile::parse("A:\n\t- 1\n\t- 2\n").unwrap();
+        let a = tpf.iter().next().unwrap().id().clone();
+        for (text, index) in [("First", 0), ("Middle", 2), ("Last", 100)] {
+            tpf.insert(
+                Item::new(ItemKind::Task, text.to_string()),
+                Position::AsNthChildOf(&a, index),
+            );
+        }
+        assert_eq!(
+            "A:\n\t- First\n\t- 1\n\t- Middle\n\t- 2\n\t- Last\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_iter_descendants() {
+        let tpf = TaskpaperFile::parse("A:\n\t- 1\n\t\tNote\n\t- 2\nB:\n\t- 3\n").unwrap();
+        for node in &tpf {
+            let expected = tpf
+                .iter_node(node.id())
+                .skip(1)
+                .map(|n| n.id().clone())
+                .collect::<Vec<_>>();
+            let descendants = tpf
+                .iter_descendants(node.id())
+                .map(|n| n.id().clone())
+                .collect::<Vec<_>>();
+            assert_eq!(expected, descendants);
+        }
+        let a = tpf.iter().next().unwrap();
+        assert_eq!(
+            vec!["1", "Note", "2"],
+            tpf.iter_descendants(a.id())
+                .map(|n| n.item().text().to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_iter_with_depth() {
+        let tpf = TaskpaperFile::parse("A:\n\t\t\t- 1\n\t\t\t\tNote\n\t- 2\nB:\n\t- 3\n").unwrap();
+        let depths = tpf
+            .iter_with_depth()
+            .map(|(depth, n)| (n.item().text().to_string(), depth))
+            .collect::<Vec<_>>();
+        let expected = [
+            ("A", 0),
+            ("1", 1),
+            ("Note", 2),
+            ("2", 1),
+            ("B", 0),
+            ("3", 1),
+        ];
+        assert_eq!(
+            expected
+                .iter()
+                .map(|(text, depth)| (text.to_string(), *depth))
+                .collect::<Vec<_>>(),
+            depths
+        );
+
+        let one = tpf.iter().find(|n| n.item().text() == "1").unwrap();
+        let depths = tpf
+            .iter_node_with_depth(one.id())
+            .map(|(depth, _)| depth)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0, 1], depths);
+    }
+
+    #[test]
+    fn test_filter_modes() {
+        let input = "A:\n\tB: @done\n\t\t- Open\n\t\t\tNote\n\t\t- Closed @done\n\t- C\n";
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        let filtered = tpf.filter("@done", FilterMode::PruneSubtree).unwrap();
+        assert_eq!(
+            vec!["B"],
+            filtered
+                .iter()
+                .map(|id| tpf[id].item().text())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, tpf[&filtered[0]].child_count());
+        assert_eq!("A:\n\t- C\n", tpf.to_string(FormatOptions::default()));
+
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        let filtered = tpf.filter("@done", FilterMode::TestAll).unwrap();
+        assert_eq!(
+            vec!["B", "Closed"],
+            filtered
+                .iter()
+                .map(|id| tpf[id].item().text())
+                .collect::<Vec<_>>()
+        );
+        assert!(filtered.iter().all(|id| tpf[id].is_leaf()));
+        assert_eq!(
+            "A:\n\t- Open\n\t\tNote\n\t- C\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        tpf.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut tpf = TaskpaperFile::parse(
+            "A:\n\tAbout A\n\tB:\n\t\t- Deep @next\n\t\t\tNote\n\t\t\t- Subtask\n\t\t- Other\n\t\t\tOther note\n\t- Sibling\nC:\n\t- Unrelated\n- Top @next\n\tTop note\n",
+        )
+        .unwrap();
+        tpf.retain("@next").unwrap();
+        assert_eq!(
+            "A:\n\tB:\n\t\t- Deep @next\n\t\t\tNote\n\tAbout A\n- Top @next\n\tTop note\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        tpf.check_invariants().unwrap();
+
+        tpf.retain("@missing").unwrap();
+        assert!(tpf.iter().next().is_none());
+    }
+
+    #[test]
+    fn test_insert_tree() {
+        let mut tpf = TaskpaperFile::parse("Other:\n").unwrap();
+        let task = |text: &str, note: &str| {
+            let mut item = Item::new(ItemKind::Task, text.to_string());
+            item.indent = 5;
+            ItemTree::with_children(
+                item,
+                vec![ItemTree::new(Item::new(ItemKind::Note, note.to_string()))],
+            )
+        };
+        let tree = ItemTree::with_children(
+            Item::new(ItemKind::Project, "Project".to_string()),
+            vec![task("First", "Note 1"), task("Second", "Note 2")],
+        );
+        let project = tpf.insert_tree(tree, Position::AsFirst);
+        assert_eq!(2, tpf[&project].child_count());
+        let indents = tpf.iter().map(|n| n.item().indent).collect::<Vec<_>>();
+        assert_eq!(vec![0, 1, 2, 1, 2, 0], indents);
+        assert_eq!(
+            "Project:\n\t- First\n\t\tNote 1\n\t- Second\n\t\tNote 2\n\nOther:\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        tpf.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_insert_node_rejects_cycles() {
+        let mut tpf = TaskpaperFile::parse("A:\n\tB:\n\t\t- C\n").unwrap();
+        let find = |tpf: &TaskpaperFile, text: &str| {
+            tpf.iter()
+                .find(|n| n.item().text() == text)
+                .map(|n| n.id().clone())
+                .unwrap()
+        };
+        let (a, c) = (find(&tpf, "A"), find(&tpf, "C"));
+        tpf.unlink_node(a.clone(), ChildrenStrategy::Remove);
+        let before: Vec<(Option<NodeId>, Vec<NodeId>)> = tpf
+            .arena
+            .iter()
+            .map(|n| (n.parent.clone(), n.children.clone()))
+            .collect();
+
+        assert!(matches!(
+            tpf.insert_node(a.clone(), Position::AsLastChildOf(&c)),
+            Err(Error::MoveIntoSubtree)
+        ));
+        let after: Vec<(Option<NodeId>, Vec<NodeId>)> = tpf
+            .arena
+            .iter()
+            .map(|n| (n.parent.clone(), n.children.clone()))
+            .collect();
+        assert_eq!(before, after);
+        assert!(tpf.nodes.is_empty());
+
+        tpf.insert_node(a, Position::AsFirst).unwrap();
+        assert_eq!(
+            "A:\n\tB:\n\t\t- C\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_rewrite_tag_values() {
+        let mut tpf = TaskpaperFile::parse(
+            "- A @due(01/02/2024) @next\n- B @due(2024-03-04) @x(01/02/2024)\n",
+        )
+        .unwrap();
+        let num_changed = tpf.rewrite_tag_values(|name, value| {
+            if name != "due" {
+                return None;
+            }
+            let parts: Vec<&str> = value.split('/').collect();
+            match parts.as_slice() {
+                [month, day, year] => Some(format!("{}-{}-{}", year, month, day)),
+                _ => None,
+            }
+        });
+        assert_eq!(1, num_changed);
+        assert_eq!(
+            "- A @next @due(2024-01-02)\n- B @due(2024-03-04) @x(01/02/2024)\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_add_and_remove_tag_where() {
+        let mut tpf =
+            TaskpaperFile::parse("- A @overdue\n- B @overdue @flagged\n- C\n- D @flagged\n")
+                .unwrap();
+        let flagged = Tag::new("flagged".to_string(), None);
+        assert_eq!(1, tpf.add_tag_where("@overdue", flagged).unwrap());
+        assert_eq!(
+            "- A @flagged @overdue\n- B @flagged @overdue\n- C\n- D @flagged\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        assert_eq!(1, tpf.remove_tag_where("not @overdue", "flagged").unwrap());
+        assert_eq!(0, tpf.remove_tag_where("@overdue", "missing").unwrap());
+        assert_eq!(
+            "- A @flagged @overdue\n- B @flagged @overdue\n- C\n- D\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        assert!(tpf
+            .add_tag_where("@(", Tag::new("x".to_string(), None))
+            .is_err());
+    }
+
+    #[test]
+    fn test_sort_nodes_by_tag_date() {
+        let mut tpf = TaskpaperFile::parse(
+            "- A @to_inbox(2020-03-01)\n- B\n- C @to_inbox(2020-01-01)\n- D @to_inbox(soon)\n\
+             - E @to_inbox(2020-02-01)\n",
+        )
+        .unwrap();
+        tpf.sort_nodes_by_tag_date("to_inbox");
+        let texts: Vec<&str> = tpf.nodes.iter().map(|id| tpf[id].item().text()).collect();
+        assert_eq!(vec!["C", "E", "A", "B", "D"], texts);
+    }
+
+    #[test]
+    fn test_ensure_ids() {
+        let mut tpf = TaskpaperFile::parse(
+            "Project:\n\t- Task @id(fixed)\n\t\tA note\n\t- Other @id\n\t- Copy @id(fixed)\n",
+        )
+        .unwrap();
+        assert_eq!(2, tpf.ensure_ids("id"));
+        assert_eq!(0, tpf.ensure_ids("id"));
+        assert_eq!(1, tpf.dedupe_ids("id"));
+        assert_eq!(0, tpf.dedupe_ids("id"));
+
+        let ids = tpf
+            .iter()
+            .filter_map(|node| node.item().tags().get_value("id").map(|v| v.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(4, ids.len());
+        assert_eq!("fixed", ids[1]);
+        assert_eq!(4, ids.iter().collect::<HashSet<_>>().len());
+        assert_eq!(36, ids[0].len());
+        assert_eq!(Some('4'), ids[0].chars().nth(14));
+
+        // Raw lines are written back unchanged and never get an id.
+        let input = "- Task\n  odd line @id(a)\n  other odd line @id(a)\n";
+        let options = ParseOptions {
+            preserve_raw: true,
+            ..ParseOptions::default()
+        };
+        let mut tpf = TaskpaperFile::parse_with_options(input, options).unwrap();
+        assert_eq!(1, tpf.ensure_ids("id"));
+        assert_eq!(0, tpf.dedupe_ids("id"));
+        assert!(tpf
+            .to_string(FormatOptions::default())
+            .ends_with("\n  odd line @id(a)\n  other odd line @id(a)\n"));
+    }
+
+    #[test]
+    fn test_sort_children_by_key() {
+        let mut tpf = TaskpaperFile::parse(
+            "A:\n\t- a3 @priority(3)\n\t- a1 @priority(1)\n\t\t- z @priority(9)\n\t\t- y @priority(8)\n\t- a2 @priority(2)\nB:\n\t- b2 @priority(2)\n\t- b1 @priority(1)\n",
+        )
+        .unwrap();
+        let priority = |node: &Node| node.item().tags().value_as_i64("priority");
+        let a = tpf.nodes[0].clone();
+        tpf.sort_children_by_key(&a, priority);
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            ..Default::default()
+        };
+        assert_eq!(
+            "A:\n\t- a1 @priority(1)\n\t\t- z @priority(9)\n\t\t- y @priority(8)\n\t- a2 @priority(2)\n\t- a3 @priority(3)\n\nB:\n\t- b2 @priority(2)\n\t- b1 @priority(1)\n",
+            tpf.to_string(options)
+        );
+        tpf.check_invariants().unwrap();
+
+        tpf.sort_all_by_key(priority);
+        assert_eq!(
+            "A:\n\t- a1 @priority(1)\n\t\t- y @priority(8)\n\t\t- z @priority(9)\n\t- a2 @priority(2)\n\t- a3 @priority(3)\n\nB:\n\t- b1 @priority(1)\n\t- b2 @priority(2)\n",
+            tpf.to_string(options)
+        );
+    }
+
+    #[test]
+    fn test_sort_keeps_notes_with_their_task() {
+        let mut tpf = TaskpaperFile::parse(
+            "A:\n\tAbout A.\n\t- Later @due(2024-02-01)\n\t\tNote of later.\n\tAlso about later.\n\t- Sooner @due(2024-01-01)\n\t\tNote of sooner.\n",
+        )
+        .unwrap();
+        let a = tpf.nodes[0].clone();
+        tpf.sort_children_by_key(&a, |node| node.item().tags().value_as_date("due"));
+        assert_eq!(
+            "A:\n\tAbout A.\n\t- Sooner @due(2024-01-01)\n\t\tNote of sooner.\n\t- Later @due(2024-02-01)\n\t\tNote of later.\n\tAlso about later.\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        tpf.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_replace_in_text() {
+        let mut tpf =
+            TaskpaperFile::parse("Old project:\n\t- See Old project\n\tNothing here\n").unwrap();
+        assert_eq!(0, tpf.replace_in_text("Missing", "New"));
+        assert_eq!(2, tpf.replace_in_text("Old", "New"));
+        assert_eq!(
+            "New project:\n\t- See New project\n\tNothing here\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        assert_eq!(1, tpf.replace_in_text("here", "there:\r\n"));
+        let texts: Vec<String> = tpf.iter().map(|n| n.item().text().to_string()).collect();
+        assert_eq!(
+            vec!["New project", "See New project", "Nothing there"],
+            texts
+        );
+    }
+
+    #[test]
+    fn test_search_child() {
+        let tpf = TaskpaperFile::parse(
+            "Has next:\n\t- Something @next\n\nNo next:\n\t- Something @waiting\n",
+        )
+        .unwrap();
+        let texts = |query: &str| -> Vec<String> {
+            tpf.search(query)
+                .unwrap()
+                .iter()
+                .map(|id| tpf[id].item().text().to_string())
+                .collect()
+        };
+        assert_eq!(vec!["Has next".to_string()], texts("@child(@next)"));
+        assert_eq!(
+            vec!["No next".to_string(), "Something".to_string()],
+            texts("not @child(@next) and not @next")
+        );
+    }
+
+    #[test]
+    fn test_search_hyphenated_tag() {
+        let tpf = TaskpaperFile::parse("- First @due-date(2020-01-01)\n- Second @due\n").unwrap();
+        let ids = tpf.search("@due-date = \"2020-01-01\"").unwrap();
+        assert_eq!(1, ids.len());
+        assert_eq!("First", tpf[&ids[0]].item().text());
+    }
+
+    #[test]
+    fn test_display() {
+        let input = include_str!("tests/simple_project_canonical_formatting.taskpaper");
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        assert_eq!(input, format!("{}", tpf));
+        assert_eq!(input, ToString::to_string(&tpf));
+    }
+
+    #[test]
+    fn test_trailing_newline() {
+        let tpf = TaskpaperFile::parse("- A task @next").unwrap();
+        let mut options = FormatOptions::default();
+        assert_eq!("- A task @next\n", tpf.to_string(options));
+        options.trailing_newline = false;
+        assert_eq!("- A task @next", tpf.to_string(options));
+    }
+
+    #[test]
+    fn test_verbatim_tags_roundtrip() {
+        let input =
+            "Project: @empty() @other(a  b)\n\t- A task @empty() @next @another(foo  bar)\n";
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        let mut options = FormatOptions {
+            verbatim_tags: true,
+            ..Default::default()
+        };
+        assert_eq!(input, tpf.to_string(options));
+
+        options.verbatim_tags = false;
+        assert_eq!(
+            "Project: @empty @other(a  b)\n\t- A task @empty @next @another(foo  bar)\n",
+            tpf.to_string(options)
+        );
+
+        // Tags are not reordered either.
+        let input = "Project: @z() @a(x  y)\n\t- A task @due(2024-01-01) @next @b()\n";
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        options.verbatim_tags = true;
+        assert_eq!(input, tpf.to_string(options));
+        options.verbatim_tags = false;
+        assert_eq!(
+            "Project: @a(x  y) @z\n\t- A task @b @next @due(2024-01-01)\n",
+            tpf.to_string(options)
+        );
+    }
+
+    #[test]
+    fn test_repeated_tags_roundtrip() {
+        let input = "- A task @link(a) @next @link(b)\n";
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        assert_eq!(
+            input,
+            tpf.to_string(FormatOptions::default().with_tags_as_written())
+        );
+        assert_eq!(
+            "- A task @next @link(a) @link(b)\n",
+            tpf.to_string(FormatOptions::default())
+        );
+        let item = tpf[&tpf.nodes[0]].item();
+        assert_eq!(
+            vec![Some("a".to_string()), Some("b".to_string())],
+            item.tags().get_all("link")
+        );
+    }
+
+    #[test]
+    fn test_item_parse_line() {
+        let task = Item::parse_line("\t\t- Buy milk @next @due(2024-01-01)");
+        assert_eq!(ItemKind::Task, task.kind);
+        assert_eq!(2, task.indent);
+        assert_eq!("Buy milk", task.text());
+        assert_eq!(Some("2024-01-01"), task.tags().get_value("due"));
+        assert!(task.tags().contains("next"));
+
+        let project = Item::parse_line("Home: @area(home)");
+        assert_eq!(ItemKind::Project, project.kind);
+        assert_eq!(0, project.indent);
+        assert_eq!("Home", project.text());
+        assert_eq!(Some("home"), project.tags().get_value("area"));
+
+        let note = Item::parse_line("\tCall before @noon");
+        assert_eq!(ItemKind::Note, note.kind);
+        assert_eq!(1, note.indent);
+        assert_eq!("Call before", note.text());
+        assert!(note.tags().contains("noon"));
+        assert_eq!(None, note.line_index());
+    }
+
+    #[test]
+    fn test_checkbox() {
+        let mut tpf = TaskpaperFile::parse("- [ ] foo\n[x] bar\n[_] baz\nNo box\n").unwrap();
+        let checkboxes = tpf.iter().map(|n| n.item().checkbox()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Some(Checkbox::Unchecked),
+                Some(Checkbox::Checked),
+                Some(Checkbox::Unchecked),
+                None
+            ],
+            checkboxes
+        );
+
+        for mut node in tpf.iter_mut() {
+            let item = node.item_mut();
+            let toggled = match item.checkbox() {
+                Some(Checkbox::Checked) => Checkbox::Unchecked,
+                Some(Checkbox::Unchecked) | None => Checkbox::Checked,
+            };
+            item.set_checkbox(Some(toggled));
+        }
+        assert_eq!(
+            "- [x] foo\n[ ] bar\n[X] baz\n[X] No box\n",
+            tpf.to_string(FormatOptions::default())
+        );
+
+        let mut item = Item::note("[X] done").build();
+        item.set_checkbox(None);
+        assert_eq!("done", item.text());
+        assert_eq!(None, item.checkbox());
+    }
+
+    #[test]
+    fn test_item_builder() {
+        let task = Item::task("Buy milk")
+            .with_tag("next", None)
+            .with_tag("due", Some("2024-01-01"))
+            .build();
+        assert_eq!(ItemKind::Task, task.kind);
+        assert_eq!(
+            "- Buy milk @next @due(2024-01-01)",
+            task.to_line(&FormatOptions::default())
+        );
+
+        let project = Item::project("Home").with_tag("area", Some("x")).build();
+        assert_eq!("Home: @area(x)", project.to_line(&FormatOptions::default()));
+        assert!(Item::note("A note").build().is_note());
+    }
+
+    #[test]
+    fn test_item_to_line() {
+        let mut tags = Tags::new();
+        tags.insert(Tag::new("due".to_string(), Some("2024-01-01".to_string())));
+        tags.insert(Tag::new("next".to_string(), None));
+        let options = FormatOptions::default();
+
+        let project = Item::new_with_tags(ItemKind::Project, "Home".to_string(), tags.clone());
+        assert_eq!("Home: @due(2024-01-01) @next", project.to_line(&options));
+
+        let task = Item::new_with_tags(ItemKind::Task, "Buy milk".to_string(), tags);
+        assert_eq!("- Buy milk @next @due(2024-01-01)", task.to_line(&options));
+        let as_written = options.with_tags_as_written();
+        assert_eq!(
+            "- Buy milk @due(2024-01-01) @next",
+            task.to_line(&as_written)
+        );
+
+        let note = Item::new(ItemKind::Note, "Some words to remember".to_string());
+        assert_eq!("Some words to remember", note.to_line(&options));
+        let wrapped = FormatOptions {
+            wrap_notes: Some(10),
+            ..options
+        };
+        assert_eq!("Some\nwords to\nremember", note.to_line(&wrapped));
+    }
+
+    #[test]
+    fn test_wrap_notes() {
+        let note =
+            "Lorem ipsum dolor sit amet, consetetur sadipscing elitr, sed diam nonumy eirmod \
+                    tempor invidunt ut labore et dolore magna aliquyam erat, sed diam voluptua. At \
+                    vero eos et accusam et justo duo dolores.";
+        assert_eq!(200, note.len());
+        let tpf = TaskpaperFile::parse(&format!("- Read this\n\t{}\n", note)).unwrap();
+        let options = FormatOptions {
+            wrap_notes: Some(80),
+            ..Default::default()
+        };
+        assert_eq!(
+            "- Read this\n\
+             \tLorem ipsum dolor sit amet, consetetur sadipscing elitr, sed diam nonumy eirmod\n\
+             \ttempor invidunt ut labore et dolore magna aliquyam erat, sed diam voluptua. At\n\
+             \tvero eos et accusam et justo duo dolores.\n",
+            tpf.to_string(options)
+        );
+        assert_eq!(
+            format!("- Read this\n\t{}\n", note),
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_wrap_notes_round_trip() {
+        let tpf = TaskpaperFile::parse("aaaa bbbb - cccc dddd eeee: ffff\n").unwrap();
+        let options = FormatOptions {
+            wrap_notes: Some(10),
+            ..Default::default()
+        };
+        let wrapped = tpf.to_string(options);
+        assert_eq!("aaaa bbbb - cccc\ndddd eeee: ffff\n", wrapped);
+        let reparsed = TaskpaperFile::parse(&wrapped).unwrap();
+        assert!(reparsed.iter().all(|node| node.item().is_note()));
+        assert_eq!(wrapped, reparsed.to_string(options));
+    }
+
+    #[test]
+    fn test_collapse_blank_lines() {
+        // The parser drops blank lines, so the blank notes that pile up from editing are inserted
+        // here directly.
+        let mut tpf = TaskpaperFile::parse("A:\n\t- Task\nB:\n\t- Task\n").unwrap();
+        let a = tpf.nodes[0].clone();
+        for _ in 0..3 {
+            tpf.insert(
+                Item::new(ItemKind::Note, "".to_string()),
+                Position::After(&a),
+            );
+        }
+        assert_eq!(
+            "A:\n\t- Task\n\nB:\n\t- Task\n",
+            tpf.to_string(FormatOptions {
+                sort: Sort::Nothing,
+                ..Default::default()
+            })
+        );
+
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            empty_line_after_project: EmptyLineAfterProject {
+                top_level: 2,
+                first_level: 1,
+                others: 0,
+            },
+            ..Default::default()
+        };
+        assert_eq!("A:\n\t- Task\n\n\nB:\n\t- Task\n", tpf.to_string(options));
+
+        // Below the first level no blank lines are kept at all.
+        let task = tpf[&a].children()[0].clone();
+        tpf.insert(
+            Item::new(ItemKind::Note, "".to_string()),
+            Position::AsLastChildOf(&task),
+        );
+        tpf.insert(
+            Item::new(ItemKind::Note, "Details".to_string()),
+            Position::AsLastChildOf(&task),
+        );
+        assert_eq!(
+            "A:\n\t- Task\n\t\tDetails\n\n\nB:\n\t- Task\n",
+            tpf.to_string(options)
+        );
+    }
+
+    #[test]
+    fn test_tag_order() {
+        let tpf = TaskpaperFile::parse("- A task @due(2018-08-05) @next @coding").unwrap();
+        let format = |tag_order| {
+            tpf.to_string(FormatOptions {
+                tag_order,
+                ..Default::default()
+            })
+        };
+        assert_eq!("- A task @coding @next @due(2018-08-05)\n", format(None));
+        assert_eq!(
+            "- A task @due(2018-08-05) @next @coding\n",
+            format(Some(TagOrder::AsWritten))
+        );
+        assert_eq!(
+            "- A task @coding @due(2018-08-05) @next\n",
+            format(Some(TagOrder::Alphabetical))
+        );
+        assert_eq!(
+            "- A task @coding @next @due(2018-08-05)\n",
+            format(Some(TagOrder::ValuelessFirst))
+        );
+    }
+
+    #[test]
+    fn test_trim_text() {
+        let input = "Project:\n\t-   padded  task   @next\n\t  padded note  \n";
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        let texts: Vec<String> = tpf.iter().map(|n| n.item().text().to_string()).collect();
+        assert_eq!(vec!["Project", "padded  task", "padded note"], texts);
+
+        let options = ParseOptions {
+            trim_text: false,
+            ..Default::default()
+        };
+        let tpf = TaskpaperFile::parse_with_options(input, options).unwrap();
+        let texts: Vec<String> = tpf.iter().map(|n| n.item().text().to_string()).collect();
+        assert_eq!(vec!["Project", "  padded  task", "  padded note  "], texts);
+        assert!(tpf
+            .iter()
+            .nth(1)
+            .unwrap()
+            .item()
+            .tags()
+            .get("next")
+            .is_some());
+    }
+
+    #[test]
+    fn test_to_string_preserving() {
+        let input = "Project:\n    - Space indented @next\n  \t  Mixed note\n\t- Tab indented\n";
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            empty_line_after_project: EmptyLineAfterProject {
+                top_level: 0,
+                first_level: 0,
+                others: 0,
+            },
+            ..Default::default()
+        };
+        assert_eq!(input, tpf.to_string_preserving(options));
+        assert_ne!(input, tpf.to_string(options));
+    }
+
+    #[test]
+    fn test_write_skips_unchanged_files() {
+        let t = DatabaseTest::new();
+        let path = t.write_file("todo.taskpaper", "Project:\n\t- Task\n");
+        let options = FormatOptions::default();
+
+        let mut tpf = TaskpaperFile::parse_file(&path).unwrap();
+        assert!(!tpf.write(&path, options).unwrap());
+        assert!(!tpf.write_atomically(&path, options).unwrap());
+        tpf.write_always(&path, options).unwrap();
+        assert_eq!("Project:\n\t- Task\n", t.read_file("todo.taskpaper"));
+
+        // Changes made by others are always noticed.
+        t.write_file("todo.taskpaper", "- Edited elsewhere\n");
+        assert!(tpf.write(&path, options).unwrap());
+        assert_eq!("Project:\n\t- Task\n", t.read_file("todo.taskpaper"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(tpf.write_atomically(&path, options).unwrap());
+        assert_eq!("Project:\n\t- Task\n", t.read_file("todo.taskpaper"));
+
+        tpf.insert(
+            Item::new(ItemKind::Task, "New".to_string()),
+            Position::AsLast,
+        );
+        assert!(tpf.write(&path, options).unwrap());
+        assert!(!tpf.write(&path, options).unwrap());
+        assert_eq!("Project:\n\t- Task\n- New\n", t.read_file("todo.taskpaper"));
+    }
+
+    #[test]
+    fn test_recompute_line_indices() {
+        let input = "---\nstyle = \"default\"\n---\nProject:\n\t- Write report\n\t\tFirst line\n\t\tSecond line\n\t- Other\nLater:\n\t- Task\n";
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        let task = tpf
+            .iter()
+            .find(|n| n.item().text() == "Write report")
+            .unwrap()
+            .id()
+            .clone();
+        let new_id = tpf.insert(
+            Item::new(ItemKind::Task, "New".to_string()),
+            Position::AsFirstChildOf(&task),
+        );
+        assert_eq!(None, tpf[&new_id].item().line_index());
+
+        let options = FormatOptions::default();
+        tpf.recompute_line_indices(options);
+        let line_indices = tpf
+            .iter()
+            .map(|n| n.item().line_index().unwrap())
+            .collect::<Vec<_>>();
+        // The empty line after 'Project' is counted.
+        assert_eq!(vec![3, 4, 5, 6, 7, 8, 10, 11], line_indices);
+        assert!(line_indices.windows(2).all(|w| w[0] < w[1]));
+
+        let output = tpf.to_string(options);
+        let lines = output.lines().collect::<Vec<_>>();
+        for node in tpf.iter() {
+            assert_eq!(
+                tpf.node_to_string(node.id()).trim_end(),
+                lines[node.item().line_index().unwrap()].trim_start()
+            );
+        }
+
+        // Blank notes that are written take up their line, the ones that are capped get none.
+        let project = tpf.nodes[0].clone();
+        let later = tpf.nodes[1].clone();
+        let blank_ids = (0..2)
+            .map(|_| {
+                tpf.insert(
+                    Item::new(ItemKind::Note, "".to_string()),
+                    Position::After(&project),
+                )
+            })
+            .collect::<Vec<_>>();
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            ..Default::default()
+        };
+        tpf.recompute_line_indices(options);
+        let mut blank_line_indices = blank_ids
+            .iter()
+            .map(|id| tpf[id].item().line_index())
+            .collect::<Vec<_>>();
+        blank_line_indices.sort();
+        assert_eq!(vec![None, Some(9)], blank_line_indices);
+        assert_eq!(Some(10), tpf[&later].item().line_index());
+    }
+
+    #[test]
+    fn test_normalize_indentation() {
+        let input =
+            "Project:\n\t\t\t- Too deep\n\t\t\t\tNote\n\t\t\t- Too deep as well\n\t- Fine\n";
+        let mut tpf = TaskpaperFile::parse(input).unwrap();
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            ..Default::default()
+        };
+        assert_eq!(input, tpf.to_string_preserving(options));
+
+        assert_eq!(3, tpf.normalize_indentation());
+        assert_eq!(0, tpf.normalize_indentation());
+        assert_eq!(
+            "Project:\n\t- Too deep\n\t\tNote\n\t- Too deep as well\n\t- Fine\n",
+            tpf.to_string_preserving(options)
+        );
+        let indents = tpf.iter().map(|n| n.item().indent).collect::<Vec<_>>();
+        assert_eq!(vec![0, 1, 2, 1, 1], indents);
+        tpf.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        let input = "Project:\n\t- Task\n\t\t- Subtask\n\tNote\nOther:\n";
+        let tpf = TaskpaperFile::parse_strict(input).unwrap();
+        assert_eq!(
+            TaskpaperFile::parse(input)
+                .unwrap()
+                .to_string(FormatOptions::default()),
+            tpf.to_string(FormatOptions::default())
+        );
+
+        let input = "Project:\n\t- Task\nOther:\n\t\t- Grandchild\n\t\t\t\t- Deeper\n";
+        match TaskpaperFile::parse_strict(input) {
+            Err(err @ Error::IndentJump { .. }) => {
+                assert_eq!("line 4: indentation jumps from 0 to 2", err.to_string())
+            }
+            other => panic!("Expected an indentation error, got {:?}", other),
+        }
+        // The lenient parse accepts the same input.
+        assert!(TaskpaperFile::parse(input).is_ok());
+    }
+
+    #[test]
+    fn test_parse_with_warnings() {
+        let input = include_str!("tests/parse_with_warnings.taskpaper");
+        let (tpf, warnings) = TaskpaperFile::parse_with_warnings(input).unwrap();
+        assert_eq!(
+            vec![
+                ParseWarning {
+                    line_index: 2,
+                    kind: ParseWarningKind::IndentJump { from: 1, to: 3 },
+                },
+                ParseWarning {
+                    line_index: 3,
+                    kind: ParseWarningKind::MixedIndentation,
+                },
+                ParseWarning {
+                    line_index: 4,
+                    kind: ParseWarningKind::UnterminatedTag,
+                },
+                ParseWarning {
+                    line_index: 5,
+                    kind: ParseWarningKind::EmptyText,
+                },
+            ],
+            warnings
+        );
+        assert_eq!(
+            "line 3: indentation jumps from 1 to 3",
+            warnings[0].to_string()
+        );
+
+        // The file is the same as without warnings.
+        assert_eq!(
+            TaskpaperFile::parse(input)
+                .unwrap()
+                .to_string(FormatOptions::default()),
+            tpf.to_string(FormatOptions::default())
+        );
+        let note = tpf.iter().last().unwrap();
+        assert_eq!("A note", note.item().text());
+        assert_eq!(Some(7), note.item().line_index());
+
+        let (_, warnings) =
+            TaskpaperFile::parse_with_warnings("\n\nProject:\n\t\t- Task\n").unwrap();
+        assert_eq!(3, warnings[0].line_index);
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let input = "---\nstyle = \"todo\"\npriority = 2\n---\nProject:\n\t- Task @next\n";
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        assert_eq!("todo", tpf.metadata()["style"]);
+        assert_eq!("2", tpf.metadata()["priority"]);
+        let items: Vec<(String, Option<usize>)> = tpf
+            .iter()
+            .map(|n| (n.item().text().to_string(), n.item().line_index()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("Project".to_string(), Some(4)),
+                ("Task".to_string(), Some(5))
+            ],
+            items
+        );
+        assert_eq!(input, tpf.to_string(FormatOptions::default()));
+
+        // Without closing delimiter, there is no front matter.
+        let tpf = TaskpaperFile::parse("---\n- Task\n").unwrap();
+        assert!(tpf.metadata().is_empty());
+        assert_eq!(2, tpf.iter().count());
+
+        // A block that is not TOML is not front matter either, but a file using '---' separators.
+        let input = "---\n- Task\n---\n- Other\n";
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        assert!(tpf.metadata().is_empty());
+        let items: Vec<(String, Option<usize>)> = tpf
+            .iter()
+            .map(|n| (n.item().text().to_string(), n.item().line_index()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("---".to_string(), Some(0)),
+                ("Task".to_string(), Some(1)),
+                ("---".to_string(), Some(2)),
+                ("Other".to_string(), Some(3))
+            ],
+            items
+        );
+    }
+
+    #[test]
+    fn test_preserve_raw_lines() {
+        let input =
+            "Project:\n\t- A task\n\t  -   weird  task @due(2018-01\n\t\t- Child task\n\t- Another task\n";
+        let tpf = TaskpaperFile::parse_with_options(
+            input,
+            ParseOptions {
+                preserve_raw: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let raw: Vec<Item> = tpf
+            .iter()
+            .filter(|n| n.item().kind == ItemKind::RawLine)
+            .map(|n| n.item().clone())
+            .collect();
+        assert_eq!(1, raw.len());
+        assert_eq!("\t  -   weird  task @due(2018-01", raw[0].text());
+        assert_eq!(input, tpf.to_string(FormatOptions::default()));
+
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        assert_ne!(input, tpf.to_string(FormatOptions::default()));
+    }
+
+    #[test]
+    fn test_tags_as_written() {
+        let input = "Project: @b @a(1)\n\t- A task @due(2018-08-05) @next @empty()\n";
+        let tpf = TaskpaperFile::parse(input).unwrap();
+        let options = FormatOptions {
+            sort: Sort::Nothing,
+            ..Default::default()
+        };
+        assert_eq!(input, tpf.to_string(options.with_tags_as_written()));
+        assert_eq!(
+            "Project: @a(1) @b\n\t- A task @empty @next @due(2018-08-05)\n",
+            tpf.to_string(options)
+        );
+    }
+
+    #[test]
+    fn test_format_task() {
+        let tpf = TaskpaperFile::parse(
+            "- Arbeit • Foo • blah @blocked(arg prs) @coding @next @done(2018-06-21)",
+        )
+        .unwrap();
+        let golden = "- Arbeit • Foo • blah @coding @next @blocked(arg prs) @done(2018-06-21)\n";
+        assert_eq!(golden, tpf.to_string(FormatOptions::default()));
+    }
+
+    #[test]
+    fn test_normalized_text() {
+        let item = Item::new(
+            ItemKind::Task,
+            "Arbeit •Foo  •   blah • •SirVer/giti".to_string(),
+        );
+        assert_eq!(
+            "Arbeit • Foo • blah • SirVer/giti",
+            item.normalized_text("•")
+        );
+        let other = Item::new(
+            ItemKind::Task,
+            "Arbeit • Foo • blah • SirVer/giti".to_string(),
+        );
+        assert_eq!(other.normalized_text("•"), item.normalized_text(" • "));
+
+        let item = Item::new(ItemKind::Task, "  Buy  milk ".to_string());
+        assert_eq!("Buy  milk", item.normalized_text(""));
+        assert_eq!("Buy  milk", item.normalized_text("  "));
+    }
+
+    #[test]
+    fn test_insert_multi_line_note() {
+        let mut tpf = TaskpaperFile::parse("Project:\n\t- A task\n").unwrap();
+        let project_id = tpf.iter().next().unwrap().id().clone();
+        let mut note = Item::new(ItemKind::Note, String::new());
+        note.text = "First line\nSecond line".to_string();
+        tpf.insert(note, Position::AsLastChildOf(&project_id));
+
+        let notes: Vec<Item> = tpf
+            .iter()
+            .filter(|n| n.item().is_note())
+            .map(|n| n.item().clone())
+            .collect();
+        assert_eq!(2, notes.len());
+        assert_eq!("First line", notes[0].text());
+        assert_eq!("Second line", notes[1].text());
+        assert_eq!(1, notes[0].indent);
+        assert_eq!(1, notes[1].indent);
+        assert_eq!(
+            "Project:\n\t- A task\n\tFirst line\n\tSecond line\n",
+            tpf.to_string(FormatOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = TaskpaperFile::parse(
+            "Project:\n\t- Unchanged @next\n\t- Removed\n\t- Retagged @due(2024-01-01)\n\t- Noted\n\t\tOld note\n",
+        )
+        .unwrap();
+        let new = TaskpaperFile::parse(
+            "Project:\n\t- Unchanged @next\n\t- Retagged @due(2024-02-01)\n\t- Added\n\t- Noted\n\t\tNew note\n",
+        )
+        .unwrap();
+        let changes = old.diff(&new);
+
+        let describe = |change: &Change| match change {
+            Change::Added(id) => format!("added {}", new[id].item().text()),
+            Change::Removed(id) => format!("removed {}", old[id].item().text()),
+            Change::Modified {
+                old: old_id,
+                new: new_id,
+            } => format!(
+                "modified {} -> {}",
+                old.node_to_string(old_id).trim(),
+                new.node_to_string(new_id).trim()
+            ),
+        };
+        assert_eq!(
+            vec![
+                "removed Removed",
+                "modified - Retagged @due(2024-01-01) -> - Retagged @due(2024-02-01)",
+                "modified - Noted -> - Noted",
+                "added Added",
+            ],
+            changes.iter().map(describe).collect::<Vec<_>>()
+        );
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn test_mirror_changes_nothing_happens_when_destination_is_newer() {
+        let test = DatabaseTest::new();
+        let source = test.write_file(
+            "source.taskpaper",
+            include_str!("tests/mirror_changes/source.taskpaper"),
+        );
+        let destination_path = test.write_file(
+            "destination.taskpaper",
+            include_str!("tests/mirror_changes/destination.taskpaper"),
+        );
+        let mut destination = TaskpaperFile::parse_file(&destination_path).unwrap();
+        mirror_changes(&source, &mut destination, MatchKey::Text).expect("Should work.");
+        assert_eq!(
+            &destination.to_string(FormatOptions::default()),
+            include_str!("tests/mirror_changes/destination.taskpaper"),
+        );
+    }
+
+    #[test]
+    fn test_mirror_changes_by_tag() {
+        let test = DatabaseTest::new();
+        let source = test.write_file(
+            "source.taskpaper",
+            "- Call the plumber about the sink @uuid(1) @next\n\tNumber is in the inbox.\n- Other task @uuid(2)\n",
+        );
+        let mut destination = TaskpaperFile::parse(
+            "Home:\n\t- Call plumber @uuid(1)\n\t- Other task @uuid(3)\n\t- Untagged\n",
+        )
+        .unwrap();
+        mirror_changes(&source, &mut destination, MatchKey::Tag("uuid".to_string()))
+            .expect("Should work");
+        assert_eq!(
+            "Home:\n\t- Call the plumber about the sink @next @uuid(1)\n\t\tNumber is in the inbox.\n\t- Other task @uuid(3)\n\t- Untagged\n",
+            destination.to_string(FormatOptions::default()),
         );
     }
 
@@ -1017,7 +4843,7 @@ mod tests {
             "source.taskpaper",
             include_str!("tests/mirror_changes/source.taskpaper"),
         );
-        mirror_changes(&source, &mut destination).expect("Should work");
+        mirror_changes(&source, &mut destination, MatchKey::Text).expect("Should work");
         assert_eq!(
             &destination.to_string(FormatOptions::default()),
             include_str!("tests/mirror_changes/destination_golden.taskpaper"),
@@ -6,7 +6,15 @@
 //! comparison => unary ( ("==" | "!=" | "<" | "<=" | ">" | ">=") unary )*
 //! unary      => "not" unary
 //!             | primary;
-//! primary    => STRING | "false" | "true" | "(" expression ")";
+//! primary    => STRING | "false" | "true" | "(" expression ")"
+//!             | "@child" "(" expression ")" | TAG | RANGE;
+//!
+//! '@child(expression)' is truish if any descendant of the item matches 'expression'. It can only
+//! be evaluated with access to the tree, i.e. through `TaskpaperFile::search`.
+//!
+//! A RANGE like 'done:2024-01-01..2024-01-07' is sugar for '@done >= "2024-01-01" and @done <=
+//! "2024-01-07"'. Either bound can be left out, e.g. 'done:2024-01-01..'. If the end is a
+//! '%Y-%m-%d' date, values with a time on that day are still inside the range.
 
 use crate::{Error, Result, Tags};
 
@@ -15,6 +23,9 @@ use crate::{Error, Result, Tags};
 enum TokenKind {
     /// A Tag, optionally with a value
     Tag(String),
+
+    /// A range of values for a tag, i.e. 'tag:start..end'. Either bound might be missing.
+    Range(String, Option<String>, Option<String>),
     LeftParen,
     RightParen,
 
@@ -62,6 +73,7 @@ impl Token {
 pub enum Expr {
     Tag(String),
     Grouping(Box<Expr>),
+    Child(Box<Expr>),
 
     NotEqual(Box<Expr>, Box<Expr>),
     Equal(Box<Expr>, Box<Expr>),
@@ -181,7 +193,20 @@ impl Expr {
         Ok(expr)
     }
 
+    /// Evaluates this expression against 'tags'. '@child' expressions are never truish, since
+    /// the descendants are not known.
     pub fn evaluate(&self, tags: &Tags) -> Value {
+        self.evaluate_with_descendants(tags, &|_| false)
+    }
+
+    /// Evaluates this expression against 'tags'. 'any_descendant' is called for '@child'
+    /// expressions with the inner expression and should return true if any descendant matches.
+    pub fn evaluate_with_descendants(
+        &self,
+        tags: &Tags,
+        any_descendant: &dyn Fn(&Expr) -> bool,
+    ) -> Value {
+        let evaluate = |e: &Expr| e.evaluate_with_descendants(tags, any_descendant);
         match self {
             Expr::Tag(name) => match tags.get(name) {
                 Some(tag) => match tag.value {
@@ -191,16 +216,17 @@ impl Expr {
                 None => Value::Undefined,
             },
             Expr::String(name) => Value::String(name.to_string()),
-            Expr::Grouping(inner) => inner.evaluate(tags),
-            Expr::NotEqual(l, r) => l.evaluate(tags).equal(&r.evaluate(tags)).not(),
-            Expr::Equal(l, r) => l.evaluate(tags).equal(&r.evaluate(tags)),
-            Expr::Greater(l, r) => l.evaluate(tags).greater(r.evaluate(tags)),
-            Expr::GreaterEqual(l, r) => l.evaluate(tags).greater_equal(r.evaluate(tags)),
-            Expr::Less(l, r) => l.evaluate(tags).less(r.evaluate(tags)),
-            Expr::LessEqual(l, r) => l.evaluate(tags).less_equal(r.evaluate(tags)),
-            Expr::Not(e) => e.evaluate(tags).not(),
-            Expr::And(l, r) => l.evaluate(tags).and(r.evaluate(tags)),
-            Expr::Or(l, r) => l.evaluate(tags).or(r.evaluate(tags)),
+            Expr::Grouping(inner) => evaluate(inner),
+            Expr::Child(inner) => Value::Bool(any_descendant(inner)),
+            Expr::NotEqual(l, r) => evaluate(l).equal(&evaluate(r)).not(),
+            Expr::Equal(l, r) => evaluate(l).equal(&evaluate(r)),
+            Expr::Greater(l, r) => evaluate(l).greater(evaluate(r)),
+            Expr::GreaterEqual(l, r) => evaluate(l).greater_equal(evaluate(r)),
+            Expr::Less(l, r) => evaluate(l).less(evaluate(r)),
+            Expr::LessEqual(l, r) => evaluate(l).less_equal(evaluate(r)),
+            Expr::Not(e) => evaluate(e).not(),
+            Expr::And(l, r) => evaluate(l).and(evaluate(r)),
+            Expr::Or(l, r) => evaluate(l).or(evaluate(r)),
             Expr::True => Value::Bool(true),
             Expr::False => Value::Bool(false),
         }
@@ -280,18 +306,20 @@ impl Parser {
         let expr = match &token.kind {
             TokenKind::False => Box::new(Expr::False),
             TokenKind::True => Box::new(Expr::True),
+            TokenKind::Tag(name) if name == "child" => {
+                let name = name.clone();
+                if !self.match_oneof(&[TokenKind::LeftParen]) {
+                    return Err(Error::QuerySyntaxError(format!(
+                        "Expect '(' after '@{}'.",
+                        name
+                    )));
+                }
+                Box::new(Expr::Child(self.closing_paren()?))
+            }
             TokenKind::Tag(name) => Box::new(Expr::Tag(name.clone())),
+            TokenKind::Range(name, start, end) => range_expr(name, start, end),
             TokenKind::String(string) => Box::new(Expr::String(string.clone())),
-            TokenKind::LeftParen => {
-                let expr = self.expression()?;
-                if !self.check(&TokenKind::RightParen) {
-                    return Err(Error::QuerySyntaxError(
-                        "Expect ')' after expression.".to_string(),
-                    ));
-                };
-                self.advance();
-                Box::new(Expr::Grouping(expr))
-            }
+            TokenKind::LeftParen => Box::new(Expr::Grouping(self.closing_paren()?)),
             _ => {
                 return Err(Error::QuerySyntaxError(format!(
                     "Invalid token: {:?}",
@@ -302,6 +330,18 @@ impl Parser {
         Ok(expr)
     }
 
+    /// Parses an expression followed by ')'.
+    fn closing_paren(&mut self) -> Result<Box<Expr>> {
+        let expr = self.expression()?;
+        if !self.check(&TokenKind::RightParen) {
+            return Err(Error::QuerySyntaxError(
+                "Expect ')' after expression.".to_string(),
+            ));
+        };
+        self.advance();
+        Ok(expr)
+    }
+
     fn match_oneof(&mut self, tokens: &[TokenKind]) -> bool {
         for t in tokens.iter() {
             if self.check(t) {
@@ -340,6 +380,36 @@ impl Parser {
     }
 }
 
+/// Desugars 'name:start..end' into comparisons on the tag 'name'.
+fn range_expr(name: &str, start: &Option<String>, end: &Option<String>) -> Box<Expr> {
+    let tag = || Box::new(Expr::Tag(name.to_string()));
+    let lower = start.as_ref().map(|start| {
+        Box::new(Expr::GreaterEqual(
+            tag(),
+            Box::new(Expr::String(start.clone())),
+        ))
+    });
+    // Values like '2024-01-07 14:00' sort after '2024-01-07', so for dates we compare against the
+    // next day instead.
+    let upper = end.as_ref().map(
+        |end| match chrono::NaiveDate::parse_from_str(end, "%Y-%m-%d") {
+            Ok(date) => {
+                let next_day = (date + chrono::Duration::days(1)).format("%Y-%m-%d");
+                Box::new(Expr::Less(
+                    tag(),
+                    Box::new(Expr::String(next_day.to_string())),
+                ))
+            }
+            Err(_) => Box::new(Expr::LessEqual(tag(), Box::new(Expr::String(end.clone())))),
+        },
+    );
+    match (lower, upper) {
+        (Some(lower), Some(upper)) => Box::new(Expr::And(lower, upper)),
+        (Some(bound), None) | (None, Some(bound)) => bound,
+        (None, None) => unreachable!("Ranges are lexed with at least one bound."),
+    }
+}
+
 #[derive(Debug)]
 pub struct CharStream {
     indices: Vec<(usize, char)>,
@@ -390,13 +460,22 @@ fn is_alpha_numeric(c: char) -> bool {
     c.is_ascii_alphanumeric() || c == '_'
 }
 
+/// Returns true if 'c' can be part of a tag name in a query. This is narrower than what items
+/// accept, since the query syntax uses characters like ':' and '.' itself.
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
 fn lex_keyword(text: &str, start: usize, stream: &mut CharStream) -> Result<Token> {
     loop {
         match stream.peek() {
-            Some(c) if is_alpha_numeric(c) => stream.advance(),
+            Some(c) if is_alpha_numeric(c) || is_tag_char(c) => stream.advance(),
             _ => break,
         };
     }
+    if stream.peek() == Some(':') {
+        return lex_range(text, start, stream);
+    }
 
     let len = stream.position() - start;
     let identifier = &text[start..start + len];
@@ -416,6 +495,40 @@ fn lex_keyword(text: &str, start: usize, stream: &mut CharStream) -> Result<Toke
     Ok(Token::new(kind, start, len))
 }
 
+/// Lexes 'tag:start..end'. The name has already been consumed, the stream points at the ':'.
+fn lex_range(text: &str, start: usize, stream: &mut CharStream) -> Result<Token> {
+    let name = text[start..stream.position()].to_string();
+    stream.advance(); // Consumes ':'
+    let value_start = stream.position();
+    loop {
+        match stream.peek() {
+            Some(c) if !c.is_whitespace() && c != '(' && c != ')' => stream.advance(),
+            _ => break,
+        };
+    }
+    let end = stream.position();
+    let value = &text[value_start..end];
+    let bound = |s: &str| {
+        if s.is_empty() {
+            None
+        } else {
+            Some(s.to_string())
+        }
+    };
+    match value.split_once("..") {
+        Some((from, to)) if !from.is_empty() || !to.is_empty() => Ok(Token::new(
+            TokenKind::Range(name, bound(from), bound(to)),
+            start,
+            end - start,
+        )),
+        _ => Err(Error::QuerySyntaxError(format!(
+            "Invalid range: '{}'. Expected '{}:start..end'.",
+            &text[start..end],
+            name
+        ))),
+    }
+}
+
 fn lex_string(text: &str, start: usize, stream: &mut CharStream) -> Result<Token> {
     loop {
         match stream.peek() {
@@ -440,7 +553,7 @@ fn lex_string(text: &str, start: usize, stream: &mut CharStream) -> Result<Token
 fn lex_tag(text: &str, start: usize, stream: &mut CharStream) -> Result<Token> {
     loop {
         match stream.peek() {
-            Some(c) if is_alpha_numeric(c) => stream.advance(),
+            Some(c) if is_tag_char(c) => stream.advance(),
             _ => break,
         };
     }
@@ -626,6 +739,16 @@ mod tests {
             ]
         );
 
+        assert_eq!(
+            lex("@due-date and @über_1").unwrap(),
+            vec![
+                Token::new(Tag("due-date".to_string()), 0, 9),
+                Token::new(And, 10, 3),
+                Token::new(Tag("über_1".to_string()), 14, 8),
+                Token::new(Eof, 22, 0)
+            ]
+        );
+
         assert_eq!(
             lex("@blub <= @bla").unwrap(),
             vec![
@@ -704,6 +827,49 @@ mod tests {
         assert_eq!(Value::Bool(true), expr.evaluate(&tags));
     }
 
+    #[test]
+    fn test_range() {
+        assert_eq!(
+            lex("done:2024-01-01..2024-01-07").unwrap(),
+            vec![
+                Token::new(
+                    Range(
+                        "done".to_string(),
+                        Some("2024-01-01".to_string()),
+                        Some("2024-01-07".to_string())
+                    ),
+                    0,
+                    27
+                ),
+                Token::new(Eof, 27, 0)
+            ]
+        );
+
+        let done = |value: &str| {
+            let mut tags = Tags::new();
+            tags.insert(crate::Tag::new("done".to_string(), Some(value.to_string())));
+            tags
+        };
+        let expr = Expr::parse("done:2024-01-01..2024-01-07").unwrap();
+        assert!(!expr.evaluate(&done("2023-12-31")).is_truish());
+        assert!(expr.evaluate(&done("2024-01-01")).is_truish());
+        assert!(expr.evaluate(&done("2024-01-07")).is_truish());
+        assert!(expr.evaluate(&done("2024-01-07 23:59")).is_truish());
+        assert!(!expr.evaluate(&done("2024-01-08")).is_truish());
+        assert!(!expr.evaluate(&Tags::new()).is_truish());
+
+        let expr = Expr::parse("done:2024-01-01.. and not @next").unwrap();
+        assert!(!expr.evaluate(&done("2023-12-31")).is_truish());
+        assert!(expr.evaluate(&done("2030-01-01")).is_truish());
+
+        let expr = Expr::parse("(done:..2024-01-07)").unwrap();
+        assert!(expr.evaluate(&done("2020-01-01")).is_truish());
+        assert!(!expr.evaluate(&done("2024-01-08")).is_truish());
+
+        assert!(Expr::parse("done:..").is_err());
+        assert!(Expr::parse("done:2024-01-01").is_err());
+    }
+
     #[test]
     fn test_tag_insertion() {
         use crate::Tag;
@@ -1,104 +1,425 @@
 use crate::search::CharStream;
-use std::collections::{btree_map::Iter as MapIter, BTreeMap};
+use crate::{Error, Result};
+use std::collections::BTreeMap;
 use std::fmt;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Tag {
     pub name: String,
     pub value: Option<String>,
 }
 
 impl Tag {
+    /// Creates a tag without checking 'name'. A name that contains characters that are not
+    /// allowed in tag names, e.g. '@a b', is written fine, but not parsed back as the same tag.
+    /// Use 'try_new' for names that are not known to be valid.
     pub fn new(name: String, value: Option<String>) -> Self {
         Tag { name, value }
     }
+
+    /// Like 'new', but returns `Error::InvalidTagName` if 'name' is empty or contains a character
+    /// that cannot be part of a tag name, like whitespace, '@', '(' or ')'.
+    pub fn try_new(name: String, value: Option<String>) -> Result<Self> {
+        let is_valid = |c: char| is_tag_name_char(c) && !c.is_whitespace();
+        if name.is_empty() || !name.chars().all(is_valid) {
+            return Err(Error::InvalidTagName(name));
+        }
+        Ok(Tag { name, value })
+    }
 }
 
 impl fmt::Display for Tag {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "@{}", self.name)?;
         if let Some(v) = &self.value {
-            write!(f, "({})", v)?;
+            write!(f, "({})", escape_value(v))?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// Returns true if 'c' can be escaped with '\' in a value, i.e. '\c' stands for 'c'.
+fn is_escapable(c: char) -> bool {
+    c == '(' || c == ')' || c == '\\'
+}
+
+/// Escapes 'value' so that it parses back unchanged. Balanced parentheses are kept as they are,
+/// unbalanced ones are escaped as '\(' and '\)'. A '\' is doubled if it would otherwise escape
+/// the next character or the closing ')' of the tag.
+fn escape_value(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut unbalanced = vec![false; chars.len()];
+    let mut open = Vec::new();
+    for (idx, c) in chars.iter().enumerate() {
+        match c {
+            '(' => open.push(idx),
+            ')' => match open.pop() {
+                Some(_) => (),
+                None => unbalanced[idx] = true,
+            },
+            _ => (),
+        }
+    }
+    for idx in open {
+        unbalanced[idx] = true;
+    }
+
+    let mut out = String::with_capacity(value.len());
+    for (idx, c) in chars.iter().enumerate() {
+        let escapes_next = match chars.get(idx + 1) {
+            Some(next) => is_escapable(*next),
+            None => true,
+        };
+        if unbalanced[idx] || (*c == '\\' && escapes_next) {
+            out.push('\\');
+        }
+        out.push(*c);
+    }
+    out
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    value: Option<String>,
+
+    /// The tag as it was written in the source file, if it was parsed and not changed since.
+    raw: Option<String>,
+}
+
+/// The tags of an item. The order in which tags were inserted (or parsed) is preserved, replacing
+/// the value of an existing tag keeps its position. A name can appear more than once, e.g.
+/// '@link(a) @link(b)', and all occurrences are kept. Comparisons do not consider the order.
+#[derive(Debug, Clone)]
 pub struct Tags {
-    tags: BTreeMap<String, Option<String>>,
+    entries: Vec<Entry>,
 }
 
+impl PartialEq for Tags {
+    fn eq(&self, other: &Tags) -> bool {
+        let sorted = |tags: &Tags| {
+            let mut pairs = tags.to_vec();
+            pairs.sort();
+            pairs
+        };
+        self.len() == other.len() && sorted(self) == sorted(other)
+    }
+}
+
+impl Eq for Tags {}
+
 impl Tags {
     pub fn new() -> Self {
         Tags {
-            tags: BTreeMap::new(),
+            entries: Vec::new(),
         }
     }
 
+    fn find(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
     pub fn remove(&mut self, name: &str) {
-        self.tags.remove(name);
+        self.entries.retain(|e| e.name != name);
     }
 
+    /// Renames all occurrences of the tag 'old' to 'new', keeping their values and positions.
+    /// Existing tags 'new' are replaced. Returns false if there is no tag 'old'.
+    pub fn rename(&mut self, old: &str, new: &str) -> bool {
+        if !self.contains(old) {
+            return false;
+        }
+        if old != new {
+            self.remove(new);
+        }
+        for entry in self.entries.iter_mut().filter(|e| e.name == old) {
+            entry.name = new.to_string();
+            entry.raw = None;
+        }
+        true
+    }
+
+    /// Sets 'tag'. If tags with the same name exist, the first one is replaced and the others are
+    /// removed.
     pub fn insert(&mut self, tag: Tag) {
-        self.tags.insert(tag.name, tag.value);
+        self.insert_entry(Entry {
+            name: tag.name,
+            value: tag.value,
+            raw: None,
+        });
+    }
+
+    /// Adds 'tag' after all other tags, even if a tag with the same name already exists.
+    pub fn push(&mut self, tag: Tag) {
+        self.entries.push(Entry {
+            name: tag.name,
+            value: tag.value,
+            raw: None,
+        });
+    }
+
+    /// Like 'push', but also remembers 'raw' as the verbatim form of the tag.
+    fn push_raw(&mut self, tag: Tag, raw: String) {
+        self.entries.push(Entry {
+            name: tag.name,
+            value: tag.value,
+            raw: Some(raw),
+        });
+    }
+
+    fn insert_entry(&mut self, entry: Entry) {
+        match self.entries.iter().position(|e| e.name == entry.name) {
+            Some(pos) => {
+                let mut rest = self.entries.split_off(pos + 1);
+                rest.retain(|e| e.name != entry.name);
+                self.entries[pos] = entry;
+                self.entries.extend(rest);
+            }
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Returns the (first) tag with 'name' exactly as it was written in the parsed source, if it
+    /// has not been changed since.
+    pub fn raw(&self, name: &str) -> Option<&str> {
+        self.find(name).and_then(|e| e.raw.as_deref())
     }
 
     pub fn contains(&self, name: &str) -> bool {
-        self.tags.contains_key(name)
+        self.find(name).is_some()
     }
 
+    /// Returns the first tag with 'name'.
     pub fn get(&self, name: &str) -> Option<Tag> {
-        self.tags.get(name).map(|v| Tag {
-            name: name.to_string(),
-            value: v.clone(),
+        self.find(name).map(|e| Tag {
+            name: e.name.clone(),
+            value: e.value.clone(),
         })
     }
 
+    /// Returns the value of the first tag with 'name'. None if the tag does not exist or has no
+    /// value.
+    pub fn get_value(&self, name: &str) -> Option<&str> {
+        self.find(name).and_then(|e| e.value.as_deref())
+    }
+
+    /// Returns the value of the first tag with 'name' parsed as '%Y-%m-%d' date. None if the tag
+    /// does not exist, has no value or the value is not a date.
+    pub fn value_as_date(&self, name: &str) -> Option<chrono::NaiveDate> {
+        self.get_value(name)
+            .and_then(|v| chrono::NaiveDate::parse_from_str(v, "%Y-%m-%d").ok())
+    }
+
+    /// Like 'value_as_date', but parses the value as integer.
+    pub fn value_as_i64(&self, name: &str) -> Option<i64> {
+        self.get_value(name).and_then(|v| v.trim().parse().ok())
+    }
+
+    /// Like 'value_as_date', but parses the value as duration, see `parse_duration`.
+    pub fn value_as_duration(&self, name: &str) -> Option<chrono::Duration> {
+        self.get_value(name)
+            .and_then(|v| crate::parse_duration(v).ok())
+    }
+
+    /// Returns the values of all tags with 'name' in insertion order.
+    pub fn get_all(&self, name: &str) -> Vec<Option<String>> {
+        self.entries
+            .iter()
+            .filter(|e| e.name == name)
+            .map(|e| e.value.clone())
+            .collect()
+    }
+
+    /// Iterates over the tags in insertion order.
     pub fn iter(&self) -> TagsIterator<'_> {
         TagsIterator {
-            iter: self.tags.iter(),
+            iter: self.entries.iter(),
+        }
+    }
+
+    /// Iterates over the tags ordered by name. Tags without value come before tags with value of
+    /// the same name, repeated tags are ordered by value. The order does not depend on the order
+    /// of insertion.
+    pub fn iter_sorted(&self) -> impl Iterator<Item = Tag> {
+        let mut tags = self.iter().collect::<Vec<_>>();
+        tags.sort_by(|a, b| (&a.name, &a.value).cmp(&(&b.name, &b.value)));
+        tags.into_iter()
+    }
+
+    /// Iterates over the tags in insertion order, together with their verbatim form if they were
+    /// parsed and not changed since.
+    pub(crate) fn iter_with_raw(&self) -> impl Iterator<Item = (Tag, Option<&str>)> {
+        self.entries
+            .iter()
+            .map(|e| (Tag::new(e.name.clone(), e.value.clone()), e.raw.as_deref()))
+    }
+
+    /// Calls 'f' with name and value of every tag that has a value and replaces the value with the
+    /// result, if any. Returns the number of tags that changed.
+    pub(crate) fn rewrite_values<F: Fn(&str, &str) -> Option<String>>(&mut self, f: F) -> usize {
+        let mut num_changed = 0;
+        for entry in &mut self.entries {
+            let new_value = match &entry.value {
+                Some(value) => match f(&entry.name, value) {
+                    Some(new_value) if new_value != *value => new_value,
+                    _ => continue,
+                },
+                None => continue,
+            };
+            entry.value = Some(new_value);
+            entry.raw = None;
+            num_changed += 1;
+        }
+        num_changed
+    }
+
+    /// Returns the tags as map from name to value. The insertion order is lost and only the first
+    /// value of repeated tags is kept.
+    pub fn to_map(&self) -> BTreeMap<String, Option<String>> {
+        let mut map = BTreeMap::new();
+        for e in &self.entries {
+            map.entry(e.name.clone()).or_insert_with(|| e.value.clone());
+        }
+        map
+    }
+
+    /// Creates tags from a map from name to value. The tags are inserted ordered by name.
+    pub fn from_map(map: BTreeMap<String, Option<String>>) -> Self {
+        Self::from_vec(map.into_iter().collect())
+    }
+
+    /// Returns the tags as (name, value) pairs in insertion order.
+    pub fn to_vec(&self) -> Vec<(String, Option<String>)> {
+        self.entries
+            .iter()
+            .map(|e| (e.name.clone(), e.value.clone()))
+            .collect()
+    }
+
+    /// Creates tags from (name, value) pairs, keeping their order. Later pairs replace the value
+    /// of earlier pairs with the same name.
+    pub fn from_vec(pairs: Vec<(String, Option<String>)>) -> Self {
+        let mut tags = Tags::new();
+        for (name, value) in pairs {
+            tags.insert(Tag::new(name, value));
         }
+        tags
     }
 
     pub fn len(&self) -> usize {
-        self.tags.len()
+        self.entries.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.tags.is_empty()
+        self.entries.is_empty()
     }
 }
 
 pub struct TagsIterator<'a> {
-    iter: MapIter<'a, String, Option<String>>,
+    iter: std::slice::Iter<'a, Entry>,
 }
 
 impl<'a> Iterator for TagsIterator<'a> {
     type Item = Tag;
 
     fn next(&mut self) -> Option<Tag> {
-        self.iter.next().map(|(k, v)| Tag {
-            name: k.to_string(),
-            value: v.clone(),
+        self.iter.next().map(|e| Tag {
+            name: e.name.clone(),
+            value: e.value.clone(),
         })
     }
 }
 
+/// Removes all tags from 'line' and returns the remaining text and the tags.
+///
+/// Tags inside the value of another tag are part of that value, i.e. '@a(@b(c))' is the tag 'a'
+/// with the value '@b(c)'. Should two found tags ever overlap, the one that starts first wins and
+/// the other is ignored, so that the line is never spliced inside an already removed range.
+/// Adjacent tags (like '@a @b') do not overlap and are both removed.
 // TODO(sirver): This could be more efficient if we'd simplified the parser to not require
 // lookback, which seems feasible. The cut out of the tags could then already be done in a single
 // iteration.
 pub fn extract_tags(mut line: String) -> (String, Tags) {
     let mut tags = Tags::new();
     let mut found = find_tags(&line);
-    found.reverse();
+    found.sort_by_key(|(_, range)| *range);
+
+    let mut ranges: Vec<(usize, usize)> = Vec::with_capacity(found.len());
     for (tag, (start, end)) in found {
+        if let Some((_, last_end)) = ranges.last() {
+            if start < *last_end {
+                continue;
+            }
+        }
+        tags.push_raw(tag, line[start..end].trim_start().to_string());
+        ranges.push((start, end));
+    }
+
+    for (start, end) in ranges.into_iter().rev() {
         line = line[0..start].to_string() + &line[end..line.len()];
-        tags.insert(tag);
     }
     (line, tags)
 }
 
+/// Returns true if 'c' can be part of a tag name. A tag name ends at a space, '(', ')' or '@', so
+/// e.g. '@due-date', '@foo.bar' and '@SirVer/giti' are tags.
+pub(crate) fn is_tag_name_char(c: char) -> bool {
+    !matches!(c, ' ' | '(' | ')' | '@')
+}
+
+/// Returns true if 'line' contains a tag whose value is opened with '(' but never closed.
+pub(crate) fn has_unterminated_value(line: &str) -> bool {
+    let mut chars = line.chars().peekable();
+    let mut previous = ' ';
+    while let Some(c) = chars.next() {
+        if c == '@' && previous == ' ' {
+            let mut name_len = 0;
+            while let Some(n) = chars.peek() {
+                if !is_tag_name_char(*n) {
+                    break;
+                }
+                name_len += 1;
+                chars.next();
+            }
+            if name_len > 0 && chars.peek() == Some(&'(') {
+                // Like the parser, read backslashes as escapes first and literally second.
+                let mut escaped = chars.clone();
+                if skip_value(&mut escaped, true) {
+                    chars = escaped;
+                } else if !skip_value(&mut chars, false) {
+                    return true;
+                }
+            }
+            previous = '@';
+            continue;
+        }
+        previous = c;
+    }
+    false
+}
+
+/// Consumes the value starting with the '(' at the front of 'chars' and returns true, or returns
+/// false if the value is never closed.
+fn skip_value(chars: &mut std::iter::Peekable<std::str::Chars>, escapes: bool) -> bool {
+    let mut depth = 0;
+    while let Some(v) = chars.next() {
+        match v {
+            '\\' if escapes && matches!(chars.peek(), Some(n) if is_escapable(*n)) => {
+                chars.next();
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return true;
+                }
+            }
+            _ => (),
+        }
+    }
+    false
+}
+
 #[derive(Debug, PartialEq)]
 enum TokenKind {
     At,
@@ -181,7 +502,7 @@ impl Parser {
             match nt.kind {
                 TokenKind::Other(c) => {
                     name.push(c);
-                    tag_ends = nt.offset + 1;
+                    tag_ends = nt.offset + c.len_utf8();
                     self.advance();
                 }
 
@@ -198,12 +519,49 @@ impl Parser {
             };
         }
 
-        // The next token is the opening ( for the value
+        // The next token is the opening ( for the value. A backslash escapes a following '(', ')'
+        // or '\'. If the value does not close that way, e.g. in '@path(C:\)', the backslashes
+        // are read literally instead.
         self.advance();
+        let value_starts = self.current;
+        let (value, value_ends) = match self.value(true) {
+            Some(v) => v,
+            None => {
+                self.current = value_starts;
+                self.value(false)?
+            }
+        };
+        Some((
+            Tag {
+                name,
+                value: if value.is_empty() { None } else { Some(value) },
+            },
+            (tag_starts, value_ends),
+        ))
+    }
+
+    /// Parses a value up to its closing ')'. Parenthesis inside the value must be balanced, so
+    /// that tags in values are kept as part of the value. Returns the value and the offset just
+    /// past the ')', or None if the line ends before the value is closed.
+    fn value(&mut self, escapes: bool) -> Option<(String, usize)> {
         let mut value = String::new();
+        let mut depth = 0;
         loop {
             let nt = self.peek();
             match nt.kind {
+                TokenKind::Other('\\') if escapes => {
+                    self.advance();
+                    let escaped = match self.peek().kind {
+                        TokenKind::LeftParen => Some('('),
+                        TokenKind::RightParen => Some(')'),
+                        TokenKind::Other('\\') => Some('\\'),
+                        _ => None,
+                    };
+                    value.push(escaped.unwrap_or('\\'));
+                    if escaped.is_some() {
+                        self.advance();
+                    }
+                }
                 TokenKind::Other(c) => {
                     value.push(c);
                     self.advance();
@@ -213,9 +571,15 @@ impl Parser {
                     self.advance();
                 }
                 TokenKind::LeftParen => {
+                    depth += 1;
                     value.push('(');
                     self.advance();
                 }
+                TokenKind::RightParen if depth > 0 => {
+                    depth -= 1;
+                    value.push(')');
+                    self.advance();
+                }
                 TokenKind::Spaces => {
                     let offset = nt.offset;
                     self.advance();
@@ -228,18 +592,10 @@ impl Parser {
                     return None;
                 }
                 TokenKind::RightParen => {
-                    tag_ends = nt.offset + 1;
-                    break;
+                    return Some((value, nt.offset + 1));
                 }
             }
         }
-        Some((
-            Tag {
-                name,
-                value: if value.is_empty() { None } else { Some(value) },
-            },
-            (tag_starts, tag_ends),
-        ))
     }
 }
 
@@ -361,6 +717,214 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_try_new() {
+        for name in &["done", "due-date", "über_1", "42", "foo.bar", "SirVer/giti"] {
+            assert!(Tag::try_new(name.to_string(), None).is_ok(), "{}", name);
+        }
+        for name in &["", "a b", "a\tb", "a@b", "a(b", "a)b", "@a"] {
+            assert!(
+                matches!(
+                    Tag::try_new(name.to_string(), Some("v".to_string())),
+                    Err(Error::InvalidTagName(_))
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let (_, mut tags) = extract_tags("- foo @c @a(1) @b".to_string());
+        tags.insert(Tag::new("a".to_string(), Some("2".to_string())));
+        tags.insert(Tag::new("d".to_string(), None));
+        let names: Vec<String> = tags.iter().map(|t| t.name).collect();
+        assert_eq!(vec!["c", "a", "b", "d"], names);
+        assert_eq!(Some("2".to_string()), tags.get("a").unwrap().value);
+    }
+
+    #[test]
+    fn test_rename() {
+        let (_, mut tags) = extract_tags("- foo @c @tickle(2020-01-01) @b".to_string());
+        assert!(tags.rename("tickle", "to_inbox"));
+        assert!(!tags.contains("tickle"));
+        assert_eq!(
+            Some("2020-01-01".to_string()),
+            tags.get("to_inbox").unwrap().value
+        );
+        let names: Vec<String> = tags.iter().map(|t| t.name).collect();
+        assert_eq!(vec!["c", "to_inbox", "b"], names);
+
+        assert!(!tags.rename("missing", "a"));
+        assert_eq!(3, tags.len());
+
+        assert!(tags.rename("to_inbox", "c"));
+        let names: Vec<String> = tags.iter().map(|t| t.name).collect();
+        assert_eq!(vec!["c", "b"], names);
+        assert_eq!(Some("2020-01-01".to_string()), tags.get("c").unwrap().value);
+    }
+
+    #[test]
+    fn test_repeated_tags() {
+        let (_, mut tags) = extract_tags("- foo @link(a) @next @link(b)".to_string());
+        assert_eq!(3, tags.len());
+        assert_eq!(Some("a"), tags.get_value("link"));
+        assert_eq!(
+            vec![Some("a".to_string()), Some("b".to_string())],
+            tags.get_all("link")
+        );
+
+        tags.insert(Tag::new("link".to_string(), Some("c".to_string())));
+        let names: Vec<String> = tags.iter().map(|t| t.name).collect();
+        assert_eq!(vec!["link", "next"], names);
+        assert_eq!(vec![Some("c".to_string())], tags.get_all("link"));
+    }
+
+    #[test]
+    fn test_iter_sorted() {
+        let (_, a) = extract_tags("- foo @c @b(2) @a @b".to_string());
+        let (_, b) = extract_tags("- foo @b @a @b(2) @c".to_string());
+        let sorted = |tags: &Tags| {
+            tags.iter_sorted()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["@a", "@b", "@b(2)", "@c"], sorted(&a));
+        assert_eq!(sorted(&a), sorted(&b));
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let (_, tags) = extract_tags(
+            "foo @due(2020-01-31) @bad(2020-13-01) @count(42) @repeat(2w) @next".to_string(),
+        );
+        assert_eq!(
+            chrono::NaiveDate::from_ymd_opt(2020, 1, 31),
+            tags.value_as_date("due")
+        );
+        assert_eq!(None, tags.value_as_date("bad"));
+        assert_eq!(None, tags.value_as_date("next"));
+        assert_eq!(None, tags.value_as_date("missing"));
+
+        assert_eq!(Some(42), tags.value_as_i64("count"));
+        assert_eq!(None, tags.value_as_i64("due"));
+
+        assert_eq!(
+            Some(chrono::Duration::weeks(2)),
+            tags.value_as_duration("repeat")
+        );
+        assert_eq!(None, tags.value_as_duration("count"));
+        assert_eq!(None, tags.value_as_duration("next"));
+    }
+
+    #[test]
+    fn test_get_value() {
+        let (_, tags) = extract_tags("foo @due(2020-01-01) @next".to_string());
+        assert_eq!(Some("2020-01-01"), tags.get_value("due"));
+        assert_eq!(None, tags.get_value("next"));
+        assert!(tags.contains("next"));
+        assert_eq!(None, tags.get_value("done"));
+    }
+
+    #[test]
+    fn test_map_and_vec_roundtrip() {
+        let (_, tags) = extract_tags("- foo @c @a(1) @b(two words)".to_string());
+
+        let map = tags.to_map();
+        assert_eq!(Some(&Some("1".to_string())), map.get("a"));
+        assert_eq!(Some(&None), map.get("c"));
+        let from_map = Tags::from_map(map);
+        assert_eq!(tags, from_map);
+        let names: Vec<String> = from_map.iter().map(|t| t.name).collect();
+        assert_eq!(vec!["a", "b", "c"], names);
+
+        let vec = tags.to_vec();
+        assert_eq!(
+            vec![
+                ("c".to_string(), None),
+                ("a".to_string(), Some("1".to_string())),
+                ("b".to_string(), Some("two words".to_string())),
+            ],
+            vec
+        );
+        assert_eq!(vec, Tags::from_vec(vec.clone()).to_vec());
+    }
+
+    #[test]
+    fn test_raw_tags() {
+        let (_, mut tags) = extract_tags("- foo @empty() @another(foo  bar) @done".to_string());
+        assert_eq!(Some("@empty()"), tags.raw("empty"));
+        assert_eq!(Some("@another(foo  bar)"), tags.raw("another"));
+        assert_eq!(Some("@done"), tags.raw("done"));
+
+        tags.insert(Tag::new("empty".to_string(), Some("full".to_string())));
+        assert_eq!(None, tags.raw("empty"));
+    }
+
+    #[test]
+    fn test_tags_in_values() {
+        let (clean, tags) = extract_tags("- foo @a(@b(c)) bar".to_string());
+        assert_eq!("- foo bar", clean);
+        assert_eq!(1, tags.len());
+        assert_eq!(
+            Some(Tag::new("a".to_string(), Some("@b(c)".to_string()))),
+            tags.get("a")
+        );
+    }
+
+    #[test]
+    fn test_parens_in_values() {
+        let (clean, tags) = extract_tags("- foo @x(a (b) c) bar".to_string());
+        assert_eq!("- foo bar", clean);
+        assert_eq!(Some("a (b) c"), tags.get_value("x"));
+        assert_eq!("@x(a (b) c)", tags.get("x").unwrap().to_string());
+
+        for value in &["see item (2", "2) done", ")(", "a\\", "a\\(b)", "C:\\dir"] {
+            let tag = Tag::new("x".to_string(), Some(value.to_string()));
+            let line = format!("- foo {} bar", tag);
+            assert!(!has_unterminated_value(&line), "{}", line);
+            let (clean, tags) = extract_tags(line.clone());
+            assert_eq!("- foo bar", clean, "{}", line);
+            assert_eq!(Some(*value), tags.get_value("x"), "{}", line);
+        }
+        assert_eq!(
+            "@x(see item \\(2)",
+            Tag::new("x".to_string(), Some("see item (2".to_string())).to_string()
+        );
+        assert_eq!(
+            "@x(C:\\dir)",
+            Tag::new("x".to_string(), Some("C:\\dir".to_string())).to_string()
+        );
+    }
+
+    #[test]
+    fn test_trailing_backslash_in_value() {
+        let line = "- foo @path(C:\\) bar";
+        assert!(!has_unterminated_value(line));
+        let (clean, tags) = extract_tags(line.to_string());
+        assert_eq!("- foo bar", clean);
+        assert_eq!(Some("C:\\"), tags.get_value("path"));
+
+        let line = "- foo @a(x\\) @b(y)";
+        assert!(!has_unterminated_value(line));
+        let (clean, tags) = extract_tags(line.to_string());
+        assert_eq!("- foo", clean);
+        assert_eq!(Some("x\\"), tags.get_value("a"));
+        assert_eq!(Some("y"), tags.get_value("b"));
+
+        assert!(has_unterminated_value("- foo @path(C:\\"));
+    }
+
+    #[test]
+    fn test_has_unterminated_value() {
+        assert!(!has_unterminated_value("- foo @done(2018-01-01) @next"));
+        assert!(!has_unterminated_value("- foo openssl@1.1(x"));
+        assert!(!has_unterminated_value("- foo @a(@b(c))"));
+        assert!(has_unterminated_value("- foo @due(2018-01"));
+        assert!(has_unterminated_value("- foo @a(@b(c)"));
+    }
+
     #[test]
     fn test_extract_tag() {
         fn check(input: &str, num_tags: usize, golden_clean: &str) {
@@ -370,6 +934,11 @@ mod tests {
         }
         check("- foo blub @done", 1, "- foo blub");
         check("- foo @check blub @done @aaa", 3, "- foo blub");
+        check("@a(@b(c))", 1, "");
+        check("- call @bob then @due-date(2020-01-01)", 2, "- call then");
+        check("- ask @foo.bar and @SirVer/giti", 2, "- ask and");
+        check("- café @über", 1, "- café");
+        check("- foo @a(@b(c)) @d", 2, "- foo");
         check("- Verschiedenes • SirVer/giti: openssl@1.1 installation instructions for buildifier, clang-format and rustfmt @done(2018-01-15)", 1,
 "- Verschiedenes • SirVer/giti: openssl@1.1 installation instructions for buildifier, clang-format and rustfmt");
     }
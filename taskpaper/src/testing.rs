@@ -23,10 +23,30 @@ impl DatabaseTest {
 
     pub fn write_file(&self, path: impl AsRef<Path>, content: &str) -> PathBuf {
         let file_path = self.dir.path().join(path);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).expect("Could not create directory");
+        }
         fs::write(&file_path, content.as_bytes()).expect("Could not write file");
         file_path
     }
 
+    /// Writes the config of the database with the settings in 'extra', which is TOML, and empty
+    /// defaults for all required sections that 'extra' does not set.
+    pub fn write_config(&self, extra: &str) -> PathBuf {
+        let mut config: toml::value::Table = toml::from_str(extra).expect("Invalid config.");
+        for section in &["aliases", "formats"] {
+            config
+                .entry(section.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+        }
+        config.entry("search".to_string()).or_insert_with(|| {
+            toml::from_str("excluded_files = []\nsaved_searches = []\n").unwrap()
+        });
+        let content =
+            toml::to_string(&toml::Value::Table(config)).expect("Could not serialize config.");
+        self.write_file(".config.toml", &content)
+    }
+
     pub fn read_file(&self, path: impl AsRef<Path>) -> String {
         let file_path = self.dir.path().join(path);
         fs::read_to_string(&file_path).expect("Could not read file")
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use structopt::StructOpt;
+use taskpaper::{Database, ExportStyle, TaskpaperFile};
+
+#[derive(StructOpt, Debug)]
+pub struct CommandLineArguments {
+    /// File to read.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Format to export to.
+    #[structopt(
+        short = "-f",
+        long = "--format",
+        default_value = "taskpaper",
+        possible_values = &["taskpaper", "outline", "markdown", "ical", "opml"]
+    )]
+    format: String,
+
+    /// Style to format with. The default is 'default'.
+    #[structopt(short = "-s", long = "--style")]
+    style: Option<String>,
+
+    /// File to write to. If not given, the export is printed to stdout.
+    #[structopt(short = "-o", long = "--output", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Write items and tags in a fixed order that does not depend on their order in the input,
+    /// which makes exports easy to diff. The input file is not changed.
+    #[structopt(long = "--canonical", alias = "stable-output")]
+    canonical: bool,
+}
+
+pub fn run(db: &Database, args: &CommandLineArguments) -> Result<()> {
+    let config = db.config()?;
+    let options = match args.style.as_ref() {
+        None => taskpaper::FormatOptions::default(),
+        Some(s) => match config.formats.get(s) {
+            Some(format) => *format,
+            None => return Err(anyhow!("Style '{}' not found.", s)),
+        },
+    };
+    let options = if args.canonical {
+        options.canonical()
+    } else {
+        options
+    };
+
+    let mut taskpaper_file = TaskpaperFile::parse_file(&args.input)?;
+    let out = match args.format.as_str() {
+        "taskpaper" => taskpaper_file.export(ExportStyle::Taskpaper, options),
+        "outline" => taskpaper_file.export(ExportStyle::Outline, options),
+        other => {
+            // These formats do not know about 'options', so we bring the items into the
+            // requested order by round tripping through taskpaper.
+            if args.canonical {
+                taskpaper_file = TaskpaperFile::parse(&taskpaper_file.to_string(options))?;
+            }
+            match other {
+                "markdown" => taskpaper_file.to_markdown(),
+                "ical" => taskpaper::tasks_to_ical(&taskpaper_file, "@due", &config.clock())?,
+                "opml" => taskpaper_file.to_opml(),
+                _ => return Err(anyhow!("Unknown export format '{}'.", other)),
+            }
+        }
+    };
+    match &args.output {
+        Some(path) => std::fs::write(path, out)?,
+        None => print!("{}", out),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taskpaper::testing::*;
+
+    #[test]
+    fn test_export_outline() {
+        let mut test = DatabaseTest::new();
+        test.write_file(".config.toml", include_str!("tests/log_done/.config.toml"));
+        let input = test.write_file("in.taskpaper", include_str!("tests/export/in.taskpaper"));
+        let output = input.with_file_name("out.txt");
+
+        let db = test.read_database();
+        let args = CommandLineArguments {
+            input,
+            format: "outline".to_string(),
+            style: None,
+            output: Some(output),
+            canonical: false,
+        };
+        run(db, &args).unwrap();
+        test.assert_eq_to_golden("src/tests/export/outline_out.txt", "out.txt");
+    }
+
+    #[test]
+    fn test_export_canonical() {
+        let mut test = DatabaseTest::new();
+        test.write_file(".config.toml", include_str!("tests/log_done/.config.toml"));
+        let first = test.write_file(
+            "first.taskpaper",
+            include_str!("tests/export/canonical_first.taskpaper"),
+        );
+        let second = test.write_file(
+            "second.taskpaper",
+            include_str!("tests/export/canonical_second.taskpaper"),
+        );
+
+        let db = test.read_database();
+        for input in [first, second] {
+            let output = input.with_extension("txt");
+            let args = CommandLineArguments {
+                input,
+                format: "taskpaper".to_string(),
+                style: None,
+                output: Some(output),
+                canonical: true,
+            };
+            run(db, &args).unwrap();
+        }
+        test.assert_eq_to_golden("src/tests/export/canonical_out.taskpaper", "first.txt");
+        test.assert_eq_to_golden("src/tests/export/canonical_out.taskpaper", "second.txt");
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::{anyhow, Result};
+use std::io::{self, BufRead, Write};
 use std::path::PathBuf;
 use structopt::StructOpt;
-use taskpaper::{Database, TaskpaperFile};
+use taskpaper::{ChildrenStrategy, Database, FilterMode, TaskpaperFile};
 
 #[derive(StructOpt, Debug)]
 pub struct CommandLineArguments {
@@ -13,11 +14,25 @@ pub struct CommandLineArguments {
     #[structopt(short = "-s", long = "--style")]
     style: String,
 
+    /// List all matches and ask which of them to delete.
+    #[structopt(long = "--interactive")]
+    interactive: bool,
+
     /// Query of the items to delete.
     query: String,
 }
 
 pub fn run(db: &Database, args: &CommandLineArguments) -> Result<()> {
+    let stdin = io::stdin();
+    filter(db, args, &mut stdin.lock(), &mut io::stdout())
+}
+
+fn filter(
+    db: &Database,
+    args: &CommandLineArguments,
+    prompt_input: &mut impl BufRead,
+    prompt_output: &mut impl Write,
+) -> Result<()> {
     let config = db.config()?;
     let style = match config.formats.get(&args.style) {
         Some(format) => *format,
@@ -25,7 +40,56 @@ pub fn run(db: &Database, args: &CommandLineArguments) -> Result<()> {
     };
 
     let mut input = TaskpaperFile::parse_file(&args.input)?;
-    input.filter(&args.query)?;
+    if args.interactive {
+        let matches = input.search(&args.query)?;
+        let choices: Vec<String> = matches
+            .iter()
+            .map(|node_id| {
+                let line = input[node_id].item().line_index().unwrap() + 1;
+                let text = input.node_to_string(node_id);
+                format!("{}:{}:{}", args.input.display(), line, text.trim_end())
+            })
+            .collect();
+        for idx in crate::select::select(&choices, prompt_input, prompt_output)? {
+            input.unlink_node(matches[idx].clone(), ChildrenStrategy::Remove);
+        }
+    } else {
+        input.filter(&args.query, FilterMode::PruneSubtree)?;
+    }
     input.write(&args.input, style)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taskpaper::testing::*;
+
+    #[test]
+    fn test_interactive_filter() {
+        let mut test = DatabaseTest::new();
+        test.write_file(".config.toml", include_str!("tests/log_done/.config.toml"));
+        let path = test.write_file(
+            "01_inbox.taskpaper",
+            "- First @next\n- Second @next\n- Third @next\n- Other\n",
+        );
+
+        let db = test.read_database();
+        let args = CommandLineArguments {
+            input: path,
+            style: "01_inbox".to_string(),
+            interactive: true,
+            query: "@next".to_string(),
+        };
+        let mut output = Vec::new();
+        filter(db, &args, &mut "2\n".as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("  3) "));
+        assert!(output.contains("01_inbox.taskpaper:2:- Second @next"));
+        assert_eq!(
+            "- First @next\n- Third @next\n- Other\n",
+            test.read_file("01_inbox.taskpaper")
+        );
+    }
+}
@@ -16,14 +16,25 @@ pub struct CommandLineArguments {
     #[structopt(parse(from_os_str))]
     input: PathBuf,
 
-    /// Style to format with. The default is 'default'.
+    /// Style to format with. The default is the 'style' in the front matter of the file or
+    /// 'default'.
     #[structopt(short = "-s", long = "--style")]
     style: Option<String>,
+
+    /// Do not reorder or rewrite any tags, only change indentation, empty lines and item order.
+    #[structopt(long = "--keep-tags")]
+    keep_tags: bool,
 }
 
 pub fn format(db: &Database, args: &CommandLineArguments) -> Result<()> {
     let config = db.config()?;
-    let style = match args.style.as_ref() {
+    let taskpaper_file = TaskpaperFile::parse_file(&args.input)?;
+    // The style given on the command line wins over the one in the front matter of the file.
+    let style_name = args
+        .style
+        .as_ref()
+        .or_else(|| taskpaper_file.metadata().get("style"));
+    let style = match style_name {
         None => taskpaper::FormatOptions::default(),
         Some(s) => match config.formats.get(s) {
             Some(format) => *format,
@@ -31,7 +42,12 @@ pub fn format(db: &Database, args: &CommandLineArguments) -> Result<()> {
         },
     };
 
-    let taskpaper_file = TaskpaperFile::parse_file(&args.input)?;
+    let style = if args.keep_tags {
+        style.with_tags_as_written()
+    } else {
+        style
+    };
+
     taskpaper_file.write(&args.input, style)?;
     Ok(())
 }
@@ -1,30 +1,134 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
-use taskpaper::Database;
+use taskpaper::{Database, Housekeeping, TaskpaperFile};
+
+const TASKPAPER_HOUSEKEEPING_STATE_FILE: &str = ".taskpaper_housekeeping_state.toml";
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Formats {
     formats: HashMap<String, taskpaper::FormatOptions>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct State {
+    last_run: Option<DateTime<Utc>>,
+}
+
 #[derive(StructOpt, Debug)]
-pub struct CommandLineArguments {}
+pub struct CommandLineArguments {
+    /// Process all files, not only the ones that changed since the last run.
+    #[structopt(long = "--all")]
+    all: bool,
+}
 
-pub fn run(db: &Database, _: &CommandLineArguments) -> Result<()> {
-    let mut inbox = db.parse_common_file(taskpaper::CommonFileKind::Inbox)?;
-    let mut todo = db.parse_common_file(taskpaper::CommonFileKind::Todo)?;
-    let mut tickle = db.parse_common_file(taskpaper::CommonFileKind::Tickle)?;
+fn modified_time(path: &Path) -> Result<DateTime<Utc>> {
+    Ok(fs::metadata(path)?.modified()?.into())
+}
+
+/// Formats all files in the database that were modified after 'since' according to 'modified', or
+/// all files if 'since' is None. Files excluded in the config are skipped. Returns the relative
+/// paths of the files that were processed.
+fn format_changed_files(
+    db: &Database,
+    since: Option<DateTime<Utc>>,
+    modified: impl Fn(&Path) -> Result<DateTime<Utc>>,
+) -> Result<Vec<PathBuf>> {
+    let config = db.config()?;
+    let mut processed = Vec::new();
+    for relative_path in db.list_files()? {
+        let is_excluded = relative_path.file_name().is_some_and(|name| {
+            config
+                .search
+                .excluded_files
+                .contains(name.to_string_lossy().as_ref())
+        });
+        if is_excluded {
+            continue;
+        }
+        let path = db.root.join(&relative_path);
+        if let Some(since) = since {
+            if modified(&path)? <= since {
+                continue;
+            }
+        }
+        let format = db.get_format_for_filename(&path)?;
+        let tpf = TaskpaperFile::parse_file(&path)?;
+        tpf.write(&path, format)?;
+        processed.push(relative_path);
+    }
+    Ok(processed)
+}
 
-    crate::tickle::tickle(&mut inbox, &mut todo, &mut tickle)?;
-    crate::extract_timeline::extract_timeline(db, &mut todo)?;
+pub fn run(db: &Database, args: &CommandLineArguments) -> Result<()> {
+    let state_path = db.root.join(TASKPAPER_HOUSEKEEPING_STATE_FILE);
+    let state = match fs::read_to_string(&state_path) {
+        Ok(data) => toml::from_str(&data)
+            .with_context(|| format!("Could not parse {}", state_path.display()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+        Err(e) => return Err(e.into()),
+    };
+    Housekeeping::default().run(db)?;
 
-    // It is very important to first write todo.taskpaper, so that the extract methods that might
-    // be run now on file change do not run into an infinite loop.
-    db.overwrite_common_file(&todo, taskpaper::CommonFileKind::Todo)?;
-    db.overwrite_common_file(&inbox, taskpaper::CommonFileKind::Inbox)?;
-    db.overwrite_common_file(&tickle, taskpaper::CommonFileKind::Tickle)?;
+    let since = if args.all { None } else { state.last_run };
+    format_changed_files(db, since, modified_time)?;
 
+    // Our own writes must not count as changes in the next run.
+    let state = State {
+        last_run: Some(Utc::now()),
+    };
+    fs::write(&state_path, toml::to_string_pretty(&state)?)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use taskpaper::testing::*;
+
+    #[test]
+    fn test_format_changed_files() {
+        let mut test = DatabaseTest::new();
+        test.write_file(
+            ".config.toml",
+            "[aliases]\n[formats]\n[search]\nexcluded_files = ['skip.taskpaper']\n\
+             saved_searches = []\n",
+        );
+        test.write_file("old.taskpaper", "- old task   @next\n");
+        test.write_file("new.taskpaper", "- new task   @next\n");
+        test.write_file("skip.taskpaper", "- skipped   @next\n");
+
+        let day = |d| Utc.with_ymd_and_hms(2024, 1, d, 12, 0, 0).unwrap();
+        let modified = |path: &Path| {
+            Ok(match path.file_name().unwrap().to_str().unwrap() {
+                "old.taskpaper" => day(1),
+                _ => day(3),
+            })
+        };
+
+        let db = test.read_database();
+        let processed = format_changed_files(db, Some(day(2)), modified).unwrap();
+        assert_eq!(vec![PathBuf::from("new.taskpaper")], processed);
+        assert_eq!("- old task   @next\n", test.read_file("old.taskpaper"));
+        assert_eq!("- new task @next\n", test.read_file("new.taskpaper"));
+
+        let db = test.read_database();
+        let mut processed = format_changed_files(db, None, modified).unwrap();
+        processed.sort();
+        assert_eq!(
+            vec![
+                PathBuf::from("new.taskpaper"),
+                PathBuf::from("old.taskpaper")
+            ],
+            processed
+        );
+        assert_eq!("- old task @next\n", test.read_file("old.taskpaper"));
+        assert_eq!("- skipped   @next\n", test.read_file("skip.taskpaper"));
+    }
+}
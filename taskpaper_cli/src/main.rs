@@ -3,14 +3,16 @@ use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
 mod check_feeds;
-mod extract_timeline;
+mod export;
 mod filter;
 mod format;
 mod housekeeping;
 mod log_done;
+mod normalize_dates;
 mod purge_tags;
+mod report;
 mod search;
-mod tickle;
+mod select;
 mod to_inbox;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,6 +45,10 @@ struct CommandLineArguments {
     #[structopt(long = "--update")]
     update: bool,
 
+    /// Do not warn about a full inbox after the command.
+    #[structopt(long = "--quiet", short = "-q")]
+    quiet: bool,
+
     #[structopt(subcommand)]
     cmd: Option<Command>,
 }
@@ -58,12 +64,18 @@ enum Command {
     #[structopt(name = "2inbox")]
     ToInbox(to_inbox::CommandLineArguments),
 
+    /// Export a taskpaper file as taskpaper, a plain text outline, Markdown, OPML or an iCalendar
+    /// of the tasks with a due date.
+    #[structopt(name = "export")]
+    Export(export::CommandLineArguments),
+
     /// Format a taskpaper file, without introducing any other changes.
     #[structopt(name = "format")]
     Format(format::CommandLineArguments),
 
     /// Housekeeping after any file has changed. This includes extracting the timeline and the
-    /// checkout, as well as formatting todo and inbox.
+    /// checkout, as well as formatting todo and inbox. All other files are only formatted if they
+    /// changed since the last run, unless --all is given.
     #[structopt(name = "housekeeping")]
     Housekeeping(housekeeping::CommandLineArguments),
 
@@ -74,10 +86,18 @@ enum Command {
     #[structopt(name = "log_done")]
     LogDone(log_done::CommandLineArguments),
 
+    /// Rewrite all tag values formatted as MM/DD/YYYY to YYYY-MM-DD.
+    #[structopt(name = "normalize_dates")]
+    NormalizeDates(normalize_dates::CommandLineArguments),
+
     /// Remove all of the given tags in the given file.
     #[structopt(name = "purge_tags")]
     PurgeTags(purge_tags::CommandLineArguments),
 
+    /// Print the items done this week (or month, or in a range) grouped by project.
+    #[structopt(name = "report")]
+    Report(report::CommandLineArguments),
+
     /// Remove all items matching the query from the input
     #[structopt(name = "filter_out")]
     Filter(filter::CommandLineArguments),
@@ -87,6 +107,29 @@ enum Command {
     CheckFeeds(check_feeds::CommandLineArguments),
 }
 
+/// Returns a warning if the inbox contains more tasks than the configured threshold.
+fn inbox_warning(db: &taskpaper::Database) -> anyhow::Result<Option<String>> {
+    let threshold = match db.config()?.inbox_warn_threshold {
+        Some(threshold) => threshold,
+        None => return Ok(None),
+    };
+    if db
+        .path_of_common_file(taskpaper::CommonFileKind::Inbox)?
+        .is_none()
+    {
+        return Ok(None);
+    }
+    let inbox = db.parse_common_file(taskpaper::CommonFileKind::Inbox)?;
+    let num_tasks = inbox.iter().filter(|n| n.item().is_task()).count();
+    if num_tasks <= threshold {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "Your inbox contains {} tasks (more than {}), time to process it.",
+        num_tasks, threshold
+    )))
+}
+
 fn main() {
     let args = CommandLineArguments::from_args();
     if args.update {
@@ -110,9 +153,12 @@ fn main() {
         Some(Command::Search(args)) => search::search(&db, &args).unwrap(),
         Some(Command::ToInbox(args)) => to_inbox::to_inbox(&db, &args).unwrap(),
         Some(Command::Format(args)) => format::format(&db, &args).unwrap(),
+        Some(Command::Export(args)) => export::run(&db, &args).unwrap(),
         Some(Command::Housekeeping(args)) => housekeeping::run(&db, &args).unwrap(),
         Some(Command::LogDone(args)) => log_done::run(&db, &args).unwrap(),
         Some(Command::PurgeTags(args)) => purge_tags::run(&db, &args).unwrap(),
+        Some(Command::NormalizeDates(args)) => normalize_dates::run(&db, &args).unwrap(),
+        Some(Command::Report(args)) => report::run(&db, &args).unwrap(),
         Some(Command::Filter(args)) => filter::run(&db, &args).unwrap(),
         Some(Command::CheckFeeds(args)) => check_feeds::run(&db, &args, &config).unwrap(),
         None => {
@@ -121,4 +167,34 @@ fn main() {
             std::process::exit(1);
         }
     }
+
+    if !args.quiet {
+        // The warning is only a nudge, a broken config or inbox must not fail the command.
+        if let Ok(Some(warning)) = inbox_warning(&db) {
+            eprintln!("{}", warning);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taskpaper::testing::*;
+
+    #[test]
+    fn test_inbox_warning() {
+        let mut test = DatabaseTest::new();
+        test.write_file(
+            ".config.toml",
+            &format!(
+                "inbox_warn_threshold = 2\n{}",
+                include_str!("tests/log_done/.config.toml")
+            ),
+        );
+        test.write_file("01_inbox.taskpaper", "- one\n- two\n\tA note\n");
+        assert_eq!(None, inbox_warning(test.read_database()).unwrap());
+
+        test.write_file("01_inbox.taskpaper", "- one\n- two\n- three\n");
+        assert!(inbox_warning(test.read_database()).unwrap().is_some());
+    }
 }
@@ -0,0 +1,49 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use taskpaper::{Database, TaskpaperFile};
+
+#[derive(StructOpt, Debug)]
+pub struct CommandLineArguments {
+    /// File to modify.
+    #[structopt(parse(from_os_str), required = true)]
+    input: PathBuf,
+
+    /// Style to format with. The default is 'default'.
+    #[structopt(short = "-s", long = "--style", default_value = "default")]
+    style: String,
+}
+
+/// Returns 'value' as '%Y-%m-%d' if it is a date formatted as '%m/%d/%Y'.
+fn normalize_date(value: &str) -> Option<String> {
+    NaiveDate::parse_from_str(value, "%m/%d/%Y")
+        .ok()
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+pub fn run(db: &Database, args: &CommandLineArguments) -> Result<()> {
+    let config = db.config()?;
+    let style = match config.formats.get(&args.style) {
+        Some(format) => *format,
+        None => return Err(anyhow!("Style '{}' not found.", args.style)),
+    };
+
+    let mut input = TaskpaperFile::parse_file(&args.input)?;
+    let num_changed = input.rewrite_tag_values(|_, value| normalize_date(value));
+    input.write(&args.input, style)?;
+    println!("Normalized {} dates.", num_changed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_date() {
+        assert_eq!(Some("2024-01-02".to_string()), normalize_date("01/02/2024"));
+        assert_eq!(None, normalize_date("2024-01-02"));
+        assert_eq!(None, normalize_date("13/02/2024"));
+    }
+}
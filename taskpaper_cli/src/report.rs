@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use structopt::StructOpt;
+use taskpaper::{
+    CommonFileKind, Database, FormatOptions, Item, ItemKind, NodeId, Position, TaskpaperFile,
+};
+
+#[derive(StructOpt, Debug)]
+pub struct CommandLineArguments {
+    /// Report the items done in the current week, starting on Monday. This is the default.
+    #[structopt(long = "--week")]
+    week: bool,
+
+    /// Report the items done in the current month.
+    #[structopt(long = "--month", conflicts_with = "week")]
+    month: bool,
+
+    /// Report the items done on or after this day (YYYY-MM-DD).
+    #[structopt(long = "--from", conflicts_with_all = &["week", "month"])]
+    from: Option<NaiveDate>,
+
+    /// Report the items done on or before this day (YYYY-MM-DD).
+    #[structopt(long = "--to", conflicts_with_all = &["week", "month"])]
+    to: Option<NaiveDate>,
+}
+
+/// The first and last day of the week or month containing 'today'.
+fn current_range(today: NaiveDate, month: bool) -> (NaiveDate, NaiveDate) {
+    if month {
+        let first = today.with_day(1).unwrap();
+        let next_month = if first.month() == 12 {
+            NaiveDate::from_ymd_opt(first.year() + 1, 1, 1)
+        } else {
+            NaiveDate::from_ymd_opt(first.year(), first.month() + 1, 1)
+        };
+        (first, next_month.unwrap().pred_opt().unwrap())
+    } else {
+        let first = today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64);
+        (first, first + chrono::Duration::days(6))
+    }
+}
+
+/// Returns the name of the group 'node_id' is reported in and the text to report it with. Items
+/// are grouped by their top level project. Items moved to the logbook by 'log_done' carry their
+/// former parents in the text, so if 'in_logbook' is set, these are grouped by the first of them
+/// instead.
+fn group_of(tpf: &TaskpaperFile, node_id: &NodeId, in_logbook: bool) -> (String, String) {
+    let text = tpf[node_id].item().text();
+    if in_logbook {
+        if let Some((project, rest)) = text.split_once(" • ") {
+            return (project.to_string(), rest.to_string());
+        }
+    }
+    let project = tpf
+        .ancestors(node_id)
+        .last()
+        .filter(|node| node.item().is_project())
+        .map(|node| node.item().text().to_string());
+    (
+        project.unwrap_or_else(|| "Other".to_string()),
+        text.to_string(),
+    )
+}
+
+/// Collects the items done between 'from' and 'to' (both inclusive, either may be open) in
+/// 'files' and formats them grouped by project.
+pub fn report(
+    db: &Database,
+    files: &HashMap<PathBuf, TaskpaperFile>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<String> {
+    let format_date = |date: Option<NaiveDate>| {
+        date.map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    };
+    let (from, to) = (format_date(from), format_date(to));
+    if from.is_empty() && to.is_empty() {
+        return Err(anyhow!("Need at least one of --from or --to."));
+    }
+    let config = db.config()?;
+    let query = format!("done:{}..{}", from, to);
+    let matches = taskpaper::db::search(query, Some("done"), &config, files)?;
+    let logbook = db.path_of_common_file(CommonFileKind::Logbook)?;
+
+    let mut groups: BTreeMap<String, Vec<Item>> = BTreeMap::new();
+    for m in &matches {
+        let in_logbook = logbook.as_ref() == Some(&db.root.join(m.path));
+        let (group, text) = group_of(m.tpf, &m.node_id, in_logbook);
+        let source = m.tpf[&m.node_id].item();
+        let kind = match source.kind {
+            ItemKind::Project => ItemKind::Project,
+            _ => ItemKind::Task,
+        };
+        let item = Item::new_with_tags(kind, text, source.tags().clone());
+        groups.entry(group).or_default().push(item);
+    }
+
+    let mut out = TaskpaperFile::new();
+    for (group, items) in groups {
+        let project = format!("{} ({})", group, items.len());
+        let project_id = out.insert(Item::new(ItemKind::Project, project), Position::AsLast);
+        for item in items {
+            out.insert(item, Position::AsLastChildOf(&project_id));
+        }
+    }
+
+    let header = match (from.as_str(), to.as_str()) {
+        (from, "") => format!("Done since {}", from),
+        ("", to) => format!("Done until {}", to),
+        (from, to) => format!("Done from {} to {}", from, to),
+    };
+    let options = FormatOptions {
+        sort: taskpaper::Sort::Nothing,
+        ..FormatOptions::default()
+    };
+    Ok(format!(
+        "{}: {} items\n\n{}",
+        header,
+        matches.len(),
+        out.to_string(options)
+    ))
+}
+
+pub fn run(db: &Database, args: &CommandLineArguments) -> Result<()> {
+    let (from, to) = if args.from.is_some() || args.to.is_some() {
+        (args.from, args.to)
+    } else {
+        // '--week' is the default, it only exists to be explicit.
+        let month = args.month && !args.week;
+        let today = db.config()?.today();
+        let (from, to) = current_range(today, month);
+        (Some(from), Some(to))
+    };
+    let files = db.parse_all_files()?;
+    print!("{}", report(db, &files, from, to)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taskpaper::testing::*;
+
+    #[test]
+    fn test_report() {
+        let mut test = DatabaseTest::new();
+        test.write_file(".config.toml", include_str!("tests/log_done/.config.toml"));
+        test.write_file(
+            "todo.taskpaper",
+            include_str!("tests/report/todo.taskpaper"),
+        );
+        test.write_file(
+            "40_logbook.taskpaper",
+            include_str!("tests/report/logbook.taskpaper"),
+        );
+
+        let db = test.read_database();
+        let files = db.parse_all_files().unwrap();
+        let from = NaiveDate::from_ymd_opt(2024, 1, 1);
+        let to = NaiveDate::from_ymd_opt(2024, 1, 7);
+        assert_eq!(
+            include_str!("tests/report/report_out.txt"),
+            report(db, &files, from, to).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_current_range() {
+        let today = NaiveDate::from_ymd_opt(2024, 2, 14).unwrap();
+        assert_eq!(
+            (
+                NaiveDate::from_ymd_opt(2024, 2, 12).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 2, 18).unwrap()
+            ),
+            current_range(today, false)
+        );
+        assert_eq!(
+            (
+                NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+            ),
+            current_range(today, true)
+        );
+    }
+}
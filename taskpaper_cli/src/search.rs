@@ -1,8 +1,10 @@
 use anyhow::Result;
+use serde::Serialize;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
-use taskpaper::{db::Database, TaskpaperFile};
+use taskpaper::db::{Database, Match};
+use taskpaper::{ItemKind, TaskpaperFile};
 
 #[derive(StructOpt, Debug)]
 pub struct CommandLineArguments {
@@ -21,10 +23,94 @@ pub struct CommandLineArguments {
     /// inverse the ordering. They will be used as keys in order of appearance.
     #[structopt(short = "-s")]
     sort_by: Option<String>,
+
+    /// Only print the query after expanding all aliases, do not search.
+    #[structopt(long = "--print-query")]
+    print_query: bool,
+
+    /// Only print the first match (by line) of every file.
+    #[structopt(long = "--one-per-file")]
+    one_per_file: bool,
+
+    /// Output format. 'json' prints an array with one object per match.
+    #[structopt(
+        short = "-f",
+        long = "--format",
+        default_value = "text",
+        possible_values = &["text", "json"]
+    )]
+    format: String,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonTag {
+    name: String,
+    value: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonMatch<'a> {
+    path: &'a Path,
+    line: usize,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    text: &'a str,
+    tags: Vec<JsonTag>,
+    done: bool,
+}
+
+/// Formats 'matches' as a JSON array, one object per match. Tags keep their order in the file and
+/// may repeat.
+fn matches_to_json(matches: &[Match<'_>]) -> Result<String> {
+    let mut out = Vec::new();
+    for m in matches {
+        let item = m.tpf[&m.node_id].item();
+        let kind = match item.kind {
+            ItemKind::Project => "project",
+            ItemKind::Task => "task",
+            ItemKind::Note => "note",
+            ItemKind::RawLine => "raw",
+        };
+        out.push(JsonMatch {
+            path: m.path,
+            line: item.line_index().unwrap() + 1,
+            kind,
+            text: item.text(),
+            tags: item
+                .tags()
+                .iter()
+                .map(|tag| JsonTag {
+                    name: tag.name,
+                    value: tag.value,
+                })
+                .collect(),
+            done: item.tags().contains("done"),
+        });
+    }
+    Ok(serde_json::to_string_pretty(&out)? + "\n")
+}
+
+/// Keeps only the match with the lowest line number of every file, retaining the order of
+/// 'matches' otherwise.
+fn first_match_per_file(matches: Vec<Match<'_>>) -> Vec<Match<'_>> {
+    let mut first_lines: HashMap<&Path, usize> = HashMap::new();
+    for m in &matches {
+        let line_no = first_lines.entry(m.path).or_insert(m.line_no);
+        *line_no = (*line_no).min(m.line_no);
+    }
+    matches
+        .into_iter()
+        .filter(|m| first_lines[m.path] == m.line_no)
+        .collect()
 }
 
 pub fn search(db: &Database, args: &CommandLineArguments) -> Result<()> {
     let config = db.config()?;
+    if args.print_query {
+        println!("{}", taskpaper::db::expand_aliases(&args.query, &config));
+        return Ok(());
+    }
+
     let mut files = HashMap::new();
     if let Some(path) = &args.input {
         files.insert(
@@ -41,6 +127,16 @@ pub fn search(db: &Database, args: &CommandLineArguments) -> Result<()> {
         &config,
         &files,
     )?;
+    let matches = if args.one_per_file {
+        first_match_per_file(matches)
+    } else {
+        matches
+    };
+
+    if args.format == "json" {
+        print!("{}", matches_to_json(&matches)?);
+        return Ok(());
+    }
 
     for m in matches {
         let item = m.tpf[&m.node_id].item();
@@ -49,9 +145,8 @@ pub fn search(db: &Database, args: &CommandLineArguments) -> Result<()> {
         print!("{}:{}:{}", m.path.display(), line, text);
         if args.descendants {
             // We skip the node itself, since that has been taken care off.
-            for child_node in m.tpf.iter_node(&m.node_id).skip(1) {
-                let indent = child_node.item().indent - item.indent;
-                let indent_str = "\t".repeat(indent as usize);
+            for (depth, child_node) in m.tpf.iter_node_with_depth(&m.node_id).skip(1) {
+                let indent_str = "\t".repeat(depth);
                 let text = m.tpf.node_to_string(child_node.id());
                 print!("{}{}", indent_str, text);
             }
@@ -60,3 +155,57 @@ pub fn search(db: &Database, args: &CommandLineArguments) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taskpaper::testing::*;
+
+    #[test]
+    fn test_first_match_per_file() {
+        let mut test = DatabaseTest::new();
+        test.write_file(".config.toml", include_str!("tests/log_done/.config.toml"));
+        test.write_file("a.taskpaper", "- a1 @foo\n- other\n- a2 @foo\n- a3 @foo\n");
+        test.write_file("b.taskpaper", "- b1 @foo\n");
+        test.write_file("c.taskpaper", "- c1\n");
+
+        let db = test.read_database();
+        let config = db.config().unwrap();
+        let files = db.parse_all_files().unwrap();
+        let matches = taskpaper::db::search("@foo".to_string(), None, &config, &files).unwrap();
+        assert_eq!(4, matches.len());
+
+        let mut found: Vec<(PathBuf, usize)> = first_match_per_file(matches)
+            .into_iter()
+            .map(|m| (m.path.to_path_buf(), m.line_no))
+            .collect();
+        found.sort();
+        assert_eq!(
+            vec![
+                (PathBuf::from("a.taskpaper"), 1),
+                (PathBuf::from("b.taskpaper"), 1)
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn test_matches_to_json() {
+        let mut test = DatabaseTest::new();
+        test.write_file(".config.toml", include_str!("tests/log_done/.config.toml"));
+        test.write_file(
+            "todo.taskpaper",
+            "Errands:\n\t- Buy milk @due(2024-01-02) @home\n\t- Call bank @done(2024-01-01)\n\t- Other\n",
+        );
+
+        let db = test.read_database();
+        let config = db.config().unwrap();
+        let files = db.parse_all_files().unwrap();
+        let matches =
+            taskpaper::db::search("@due or @done".to_string(), None, &config, &files).unwrap();
+        assert_eq!(
+            include_str!("tests/search/matches.json"),
+            matches_to_json(&matches).unwrap()
+        );
+    }
+}
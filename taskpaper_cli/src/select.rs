@@ -0,0 +1,72 @@
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+/// Prints 'choices' numbered to 'output' and reads the selection from 'input'. The selection is a
+/// list of numbers separated by ',' or whitespace, or 'a' for all choices. Invalid input asks
+/// again, end of input selects nothing. Returns the sorted indices of the chosen entries.
+pub fn select(
+    choices: &[String],
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Vec<usize>> {
+    for (idx, choice) in choices.iter().enumerate() {
+        writeln!(output, "{:>3}) {}", idx + 1, choice)?;
+    }
+    loop {
+        write!(output, "Select (e.g. '1,3' or 'a' for all): ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(Vec::new());
+        }
+        if let Some(selection) = parse_selection(line.trim(), choices.len()) {
+            return Ok(selection);
+        }
+        writeln!(output, "Invalid selection '{}'.", line.trim())?;
+    }
+}
+
+fn parse_selection(line: &str, num_choices: usize) -> Option<Vec<usize>> {
+    if line == "a" {
+        return Some((0..num_choices).collect());
+    }
+    let mut selection = Vec::new();
+    for entry in line.split(|c: char| c == ',' || c.is_whitespace()) {
+        if entry.is_empty() {
+            continue;
+        }
+        match entry.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= num_choices => selection.push(n - 1),
+            _ => return None,
+        }
+    }
+    selection.sort_unstable();
+    selection.dedup();
+    Some(selection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(Some(vec![0, 2]), parse_selection("3, 1", 3));
+        assert_eq!(Some(vec![0, 1, 2]), parse_selection("a", 3));
+        assert_eq!(Some(vec![]), parse_selection("", 3));
+        assert_eq!(None, parse_selection("4", 3));
+        assert_eq!(None, parse_selection("0", 3));
+        assert_eq!(None, parse_selection("x", 3));
+    }
+
+    #[test]
+    fn test_select_asks_again() {
+        let choices = vec!["a".to_string(), "b".to_string()];
+        let mut output = Vec::new();
+        let selection = select(&choices, &mut "7\n2\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(vec![1], selection);
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Invalid selection '7'."));
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
 #[cfg(target_os = "macos")]
 use copypasta::{ClipboardContext, ClipboardProvider};
+use lazy_static::lazy_static;
 #[cfg(target_os = "macos")]
 use osascript::JavaScript;
+use std::collections::HashMap;
 use std::io::{self, BufRead};
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -45,6 +48,59 @@ pub struct CommandLineArguments {
     /// Tags to add to this item (including @).
     #[structopt(long = "--tag")]
     tags: Vec<String>,
+
+    /// Name of a template from the config to use as item instead of reading the input.
+    #[structopt(long = "--template")]
+    template: Option<String>,
+
+    /// Values for the placeholders of the template as 'name=value'. Values like '+2d' are
+    /// replaced by the date that far from today.
+    #[structopt(long = "--set")]
+    values: Vec<String>,
+}
+
+/// Replaces all '{name}' placeholders in 'template' with the 'name=value' pairs from 'values'.
+/// Values like '+2d', see `taskpaper::parse_duration`, are turned into the date that far from
+/// 'today'. Values are inserted as they are, placeholders in them are not expanded.
+fn expand_template(template: &str, values: &[String], today: NaiveDate) -> Result<String> {
+    lazy_static! {
+        static ref PLACEHOLDER: regex::Regex = regex::Regex::new(r"\{(\w+)\}").unwrap();
+        static ref RELATIVE_DATE: regex::Regex = regex::Regex::new(r"^\+(\d+[dwmy])$").unwrap();
+    };
+
+    let mut resolved = HashMap::new();
+    for entry in values {
+        let (name, value) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid value '{}', expected 'name=value'.", entry))?;
+        let value = match RELATIVE_DATE.captures(value) {
+            Some(captures) => {
+                let duration = &captures[1];
+                today
+                    .checked_add_signed(taskpaper::parse_duration(duration)?)
+                    .ok_or_else(|| anyhow!("Duration '{}' is too large.", duration))?
+                    .format("%Y-%m-%d")
+                    .to_string()
+            }
+            None => value.to_string(),
+        };
+        resolved.insert(name, value);
+    }
+
+    let mut missing = None;
+    let text = PLACEHOLDER.replace_all(template, |captures: &regex::Captures| {
+        match resolved.get(&captures[1]) {
+            Some(value) => value.clone(),
+            None => {
+                missing.get_or_insert_with(|| captures[1].to_string());
+                String::new()
+            }
+        }
+    });
+    if let Some(name) = missing {
+        return Err(anyhow!("No value for placeholder '{}'.", name));
+    }
+    Ok(text.into_owned())
 }
 
 #[cfg(target_os = "macos")]
@@ -166,7 +222,7 @@ pub fn to_inbox(db: &Database, args: &CommandLineArguments) -> Result<()> {
                 taskpaper::TaskpaperFile::new()
             }
         }
-        None => db.parse_common_file(taskpaper::CommonFileKind::Inbox)?,
+        None => db.parse_or_create_common_file(taskpaper::CommonFileKind::Inbox)?,
     };
 
     let node_id;
@@ -189,7 +245,13 @@ pub fn to_inbox(db: &Database, args: &CommandLineArguments) -> Result<()> {
         }
     };
 
-    let input: Vec<String> = if args.prompt {
+    let input: Vec<String> = if let Some(name) = &args.template {
+        let template = config
+            .templates
+            .get(name)
+            .ok_or_else(|| anyhow!("Template '{}' not found.", name))?;
+        vec![expand_template(template, &args.values, config.today())?]
+    } else if args.prompt {
         let reply = rprompt::prompt_reply_stdout("Task> ")?;
         vec![reply]
     } else {
@@ -223,7 +285,42 @@ pub fn to_inbox(db: &Database, args: &CommandLineArguments) -> Result<()> {
             };
             tpf.write(f, style)?;
         }
-        None => db.overwrite_common_file(&tpf, taskpaper::CommonFileKind::Inbox)?,
+        None => {
+            db.overwrite_common_file(&tpf, taskpaper::CommonFileKind::Inbox)?;
+        }
     };
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_template() {
+        let template = "- Meeting with {who} @due({when})";
+        let today = NaiveDate::from_ymd_opt(2020, 2, 28).unwrap();
+        let values = vec!["who=Alice".to_string(), "when=+2d".to_string()];
+        assert_eq!(
+            "- Meeting with Alice @due(2020-03-01)",
+            expand_template(template, &values, today).unwrap()
+        );
+        assert!(expand_template(template, &values[..1], today).is_err());
+        assert!(expand_template(template, &["who".to_string()], today).is_err());
+
+        // Only '+' followed by a duration is a relative date, and values are not expanded again.
+        let values = vec![
+            "who=Bob at +4917012345".to_string(),
+            "when={who}".to_string(),
+        ];
+        assert_eq!(
+            "- Meeting with Bob at +4917012345 @due({who})",
+            expand_template(template, &values, today).unwrap()
+        );
+        let values = vec!["who=+4917012345".to_string(), "when=+1w".to_string()];
+        assert_eq!(
+            "- Meeting with +4917012345 @due(2020-03-06)",
+            expand_template(template, &values, today).unwrap()
+        );
+    }
+}